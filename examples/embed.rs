@@ -0,0 +1,55 @@
+// runs an rba program from rust, handing it a value and reading the answer back,
+// then one that talks over stdin and stdout with buffers instead of the terminal
+use std::collections::HashMap;
+use std::io::Cursor;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Capture, Program, Redirect};
+
+const SRC: &str = "
+MOV 1 X;
+MOV 0 Z;
+
+LABEL: loop;
+MOV X Y;
+MOV Z X;
+ADD Z Y;
+
+SUB n 1;
+JNZ n loop;
+";
+
+const ECHO: &str = r#"
+INC io;
+
+CALL malloc 64 buf;
+CALL read 2, buf, 64 len;
+CALL write 0, "you said: ", 10;
+CALL write 0, buf, len;
+OUT len;
+"#;
+
+fn main() {
+    let program = Program::parse(SRC).unwrap_or_else(|e| panic!("{e}"));
+
+    // compiling stops at the errors check would report
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap_or_else(|errors| panic!("{}", errors[0]));
+    for n in [1, 10, 50] {
+        engine.set("n", n);
+        unsafe { engine.run() }.unwrap();
+        println!("fib({n}) = {}", engine.get("Z").unwrap());
+    }
+
+    let echo = Program::parse(ECHO).unwrap_or_else(|e| panic!("{e}"));
+
+    let out = Capture::new();
+    let mut engine = echo.compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone()).set_stdin(Cursor::new("hello\n"));
+    unsafe { engine.run() }.unwrap();
+    println!("jit wrote {:?}", String::from_utf8_lossy(&out.take()));
+
+    // the interpreter's bytecode holds on to them the same way
+    let mut bytecode = echo.bytecode(BorrowingModuleProvider).unwrap();
+    bytecode.set_stdout(out.clone()).set_stdin(Cursor::new("again\n"));
+    unsafe { bytecode.run(&mut HashMap::new()) }.unwrap();
+    println!("interpreter wrote {:?}", out.text());
+}
@@ -0,0 +1,38 @@
+// rust closures registered with a Host are the `host` module, callable from both backends
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use rba::{Host, Program, Word};
+
+const SRC: &str = "
+INC host;
+
+MOV 0 i;
+LABEL: loop;
+CALL square i sq;
+CALL log_metric i, sq;
+ADD i 1;
+JLT i, 5, loop;
+
+RCALL total t;
+OUT t;
+";
+
+fn main() {
+    let metrics = Rc::new(RefCell::new(Vec::new()));
+
+    let mut host = Host::new();
+    let log = metrics.clone();
+    host.register("square", |a: Word| a * a)
+        .register("log_metric", move |a: Word, b: Word| log.borrow_mut().push((a, b)));
+    let sum = metrics.clone();
+    host.register("total", move || sum.borrow().iter().map(|(_, b)| b).sum::<Word>());
+
+    let program = Program::parse(SRC).unwrap_or_else(|e| panic!("{e}"));
+    let mut engine = program.compile(&host).unwrap_or_else(|errors| panic!("{}", errors[0]));
+    unsafe { engine.run() }.unwrap();
+    println!("jit: {:?}", metrics.take());
+
+    unsafe { program.interpret(&host, &mut HashMap::new()).unwrap() }.unwrap();
+    println!("interpreter: {:?}", metrics.take());
+}
@@ -0,0 +1,16 @@
+// a module in a shared library, `cargo build --example math` and run examples/math.rbasm
+use rba_runtime::{module, Error, Word};
+
+pub struct Math;
+
+#[module(math)]
+impl Math {
+    fn gcd(a: Word, b: Word) -> Word {
+        if b == 0 { a } else { Math::gcd(b, a % b) }
+    }
+    fn isqrt(n: Word) -> Word { (n as f64).sqrt() as Word }
+    fn show(a: Word) { println!("math says {a}") }
+    fn div(a: Word, b: Word) -> Result<Word, Error> {
+        a.checked_div(b).ok_or_else(|| Error::new(Error::INVALID, "division by zero"))
+    }
+}
@@ -1,8 +1,39 @@
 use proc_macro::{self, TokenStream};
-use quote::quote;
-use syn::{parse_macro_input,  ItemImpl, ImplItem};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input,  ItemImpl, ImplItem, ImplItemFn, FnArg, ReturnType, Type};
 use syn::__private::TokenStream2;
 
+// the Kind of every parameter, going off the name of its type
+fn kinds(f: &ImplItemFn) -> Vec<TokenStream2> {
+    f.sig.inputs.iter()
+        .map(|arg| {
+            let ty = match arg {
+                FnArg::Typed(pat) => &pat.ty,
+                FnArg::Receiver(_) => panic!("module functions can't take self")
+            };
+            let name = match &**ty {
+                Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+                _ => None
+            };
+            match name.as_deref() {
+                Some("Word") => quote! { ::rba_runtime::Kind::Word },
+                Some("Addr") => quote! { ::rba_runtime::Kind::Addr },
+                Some("Wp") => quote! { ::rba_runtime::Kind::Wp },
+                Some("Len") => quote! { ::rba_runtime::Kind::Len },
+                _ => panic!("parameters of module functions have to be a Word, Addr, Len or Wp")
+            }
+        }).collect()
+}
+
+// whether a CALL gets a value back, a Result returns whatever it wraps
+fn returns(f: &ImplItemFn) -> TokenStream2 {
+    match &f.sig.output {
+        ReturnType::Type(_, ty) => quote! { <#ty as ::rba_runtime::Returns>::RETURNS },
+        ReturnType::Default => quote! { false }
+    }
+}
+
+// everything from the runtime goes by its full path, a module only has to import the macro
 #[proc_macro_attribute]
 pub fn module(name: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemImpl);
@@ -20,22 +51,97 @@ pub fn module(name: TokenStream, item: TokenStream) -> TokenStream {
         }).collect();
 
     let t = input.self_ty.clone();
+    let name = name.to_string();
+
+    // every function gets a C ABI wrapper called rba_<module>_<function>, that's what the JIT calls
+    // and what ahead of time compiled programs link against (plain names would clash with libc)
+    let wrappers: TokenStream2 = methods.iter()
+        .map(|f| {
+            let n = &f.sig.ident;
+            let wrapper = format_ident!("rba_{}_{}", name, n);
+            let args: Vec<_> = (0..f.sig.inputs.len()).map(|i| format_ident!("a{}", i)).collect();
+            let types: Vec<_> = f.sig.inputs.iter()
+                .map(|arg| match arg {
+                    FnArg::Typed(pat) => pat.ty.clone(),
+                    FnArg::Receiver(_) => panic!("module functions can't take self")
+                }).collect();
+            let function = format!("{name}::{n}");
+
+            // a Result's error goes to the program instead of out of the wrapper
+            match &f.sig.output {
+                ReturnType::Type(_, ty) => quote! {
+                    #[no_mangle]
+                    extern "C" fn #wrapper(#(#args: #types),*) -> <#ty as ::rba_runtime::Returns>::Output {
+                        ::rba_runtime::Returns::output(#t::#n(#(#args),*), #function)
+                    }
+                },
+                ReturnType::Default => quote! {
+                    #[no_mangle]
+                    extern "C" fn #wrapper(#(#args: #types),*) {
+                        #t::#n(#(#args),*)
+                    }
+                }
+            }
+        }).collect();
 
     let insert: TokenStream2 = idents.iter()
         .map(|n| {
-            let name = n.to_string();
+            let name_str = n.to_string();
+            let wrapper = format_ident!("rba_{}_{}", name, n);
             quote! {
-                (#name, (#t::#n) as *const u8),
+                (#name_str, #wrapper as *const u8),
             }
         }).collect();
     let l = idents.len();
 
-    let name = name.to_string();
+    let sigs: TokenStream2 = methods.iter()
+        .map(|f| {
+            let name = f.sig.ident.to_string();
+            let params = f.sig.inputs.len();
+            let returns = returns(f);
+            let kinds = kinds(f);
+            quote! {
+                (#name, ::rba_runtime::FnSig { params: #params, returns: #returns, kinds: &[#(#kinds),*] }),
+            }
+        }).collect();
+
+    // the same functions again, in a form a shared library can hand to a program that loads it
+    let descriptor = format_ident!("rba_module_{}", name);
+    let c_name = format!("{name}\0");
+    let functions: TokenStream2 = methods.iter()
+        .map(|f| {
+            let c_fn = format!("{}\0", f.sig.ident);
+            let wrapper = format_ident!("rba_{}_{}", name, f.sig.ident);
+            let params = f.sig.inputs.len();
+            let returns = returns(f);
+            let kinds = kinds(f);
+            quote! {
+                ::rba_runtime::FunctionDescriptor {
+                    name: #c_fn.as_ptr() as *const _,
+                    params: #params,
+                    kinds: (&[#(#kinds),*]).as_ptr(),
+                    returns: #returns,
+                    ptr: #wrapper as *const u8
+                },
+            }
+        }).collect();
+
     let output = quote! {
         #input
 
-        impl Module<&'static str, [(&'static str, *const u8); #l]> for #t {
+        #wrappers
+
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static #descriptor: ::rba_runtime::ModuleDescriptor = {
+            static FUNCTIONS: [::rba_runtime::FunctionDescriptor; #l] = [#functions];
+            ::rba_runtime::ModuleDescriptor { abi: ::rba_runtime::ABI_VERSION, name: #c_name.as_ptr() as *const _, functions: FUNCTIONS.as_ptr(), len: #l,
+                               forward: ::rba_runtime::rba_forward_errors }
+        };
+
+        impl ::rba_runtime::Module<&'static str, [(&'static str, *const u8); #l]> for #t {
             const NAME: &'static str = #name;
+            const SIGNATURES: &'static [(&'static str, ::rba_runtime::FnSig)] = &[#sigs];
 
             fn symbols() -> [(&'static str, *const u8); #l] {
                 [#insert]
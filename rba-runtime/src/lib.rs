@@ -0,0 +1,845 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::c_char;
+use std::fs::{self, File, OpenOptions, ReadDir};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::{fmt, mem, ptr};
+pub use rba_derive::module;
+
+// what #[module] expands to names the runtime by its crate path, std and io are expanded in here
+extern crate self as rba_runtime;
+
+pub type Word = u64;
+pub type Addr = Word;
+// how many bytes there are at the Addr parameter right before it
+pub type Len = Word;
+
+// what a function looks like from a CALL, every parameter and the return value is a Word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FnSig {
+    pub params: usize,
+    pub returns: bool,
+    // what each parameter is meant to hold, empty if nothing is known besides how many there are
+    pub kinds: &'static [Kind]
+}
+
+impl FnSig {
+    pub const fn new(params: usize, returns: bool) -> FnSig {
+        FnSig { params, returns, kinds: &[] }
+    }
+
+    pub fn kind(&self, param: usize) -> Kind {
+        self.kinds.get(param).copied().unwrap_or(Kind::Word)
+    }
+}
+
+// all of these are passed as a Word, the difference is only in what a program should pass
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Kind {
+    Word,
+    Addr,
+    // a handle from io, stdout stderr stdin or an open file
+    Wp,
+    // the length of the Addr before it, a sandbox checks the whole range is inside its memory
+    Len
+}
+
+pub trait Module<K: Into<String>, T: IntoIterator<Item=(K, *const u8)>> {
+    const NAME: &'static str;
+    const SIGNATURES: &'static [(&'static str, FnSig)];
+
+    fn symbols() -> T;
+}
+
+// bumped whenever the descriptors below change shape
+pub const ABI_VERSION: u32 = 4;
+
+// every module exports one of these as rba_module_<name>, a shared library named
+// after the module (libfoo.so for foo) can be loaded with INC
+#[repr(C)]
+pub struct ModuleDescriptor {
+    pub abi: u32,
+    pub name: *const c_char,
+    pub functions: *const FunctionDescriptor,
+    pub len: usize,
+    // the program that loads the module hands its `rba_raise` to this, the library has
+    // its own copy of the runtime and the errors it raises have to end up in the program's
+    pub forward: extern "C" fn(RaiseFn)
+}
+
+#[repr(C)]
+pub struct FunctionDescriptor {
+    pub name: *const c_char,
+    pub params: usize,
+    // `params` of them
+    pub kinds: *const Kind,
+    pub returns: bool,
+    pub ptr: *const u8
+}
+
+// only ever points to static data
+unsafe impl Sync for ModuleDescriptor { }
+unsafe impl Sync for FunctionDescriptor { }
+
+// where OUT, std and io handles 0, 1 and 2 write to and read from, the process' own
+// unless an embedder swaps them out with `with_streams`
+pub struct Streams {
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+    pub stdin: Box<dyn Read>
+}
+
+impl Default for Streams {
+    fn default() -> Streams {
+        Streams { stdout: Box::new(io::stdout()), stderr: Box::new(io::stderr()), stdin: Box::new(io::stdin()) }
+    }
+}
+
+thread_local! {
+    static STREAMS: RefCell<Streams> = RefCell::new(Streams::default());
+}
+
+// runs `f` with `streams` as the program's streams, the previous ones are put back after (even on a panic)
+pub fn with_streams<T>(streams: &mut Streams, f: impl FnOnce() -> T) -> T {
+    struct Restore<'a>(&'a mut Streams);
+
+    impl Drop for Restore<'_> {
+        fn drop(&mut self) {
+            STREAMS.with(|s| mem::swap(&mut *s.borrow_mut(), self.0));
+        }
+    }
+
+    STREAMS.with(|s| mem::swap(&mut *s.borrow_mut(), streams));
+    let _restore = Restore(streams);
+    f()
+}
+
+fn streams<T>(f: impl FnOnce(&mut Streams) -> T) -> T {
+    STREAMS.with(|s| f(&mut s.borrow_mut()))
+}
+
+// a stream to hand to `Streams` that can be read back once the program is done, clones share the buffer
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    // empties the buffer, giving back what was in it
+    pub fn take(&self) -> Vec<u8> {
+        mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// what went wrong in a module function or the program itself, `code` is what `CALL errno` gives
+// the program and is never 0
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub code: Word,
+    pub message: String,
+    // where the program was when it gave up on the error, an index into its instructions
+    // (a FN's header and then its body count in the order they're written) and the line that's on
+    pub at: Option<usize>,
+    pub line: Option<usize>
+}
+
+impl Error {
+    // codes for errors that don't come from the os, well clear of any errno
+    pub const OTHER: Word = 0x1_0000;
+    pub const BAD_HANDLE: Word = 0x1_0001;
+    pub const UNSUPPORTED: Word = 0x1_0002;
+    pub const INVALID: Word = 0x1_0003;
+    // traps, raised by the program instead of a function
+    pub const DIV_ZERO: Word = 0x1_0004;
+    pub const BAD_ADDRESS: Word = 0x1_0005;
+    // sandboxed programs only
+    pub const OUT_OF_BOUNDS: Word = 0x1_0006;
+    pub const DENIED: Word = 0x1_0007;
+    // can't be caught, the program stops
+    pub const OUT_OF_FUEL: Word = 0x1_0008;
+    // a FN called with MAX_DEPTH calls already going
+    pub const TOO_DEEP: Word = 0x1_0009;
+
+    pub fn new(code: Word, message: impl Into<String>) -> Error {
+        // 0 is what a call that went fine gives
+        Error { code: code.max(1), message: message.into(), at: None, line: None }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        match (self.at, self.line) {
+            (Some(at), Some(line)) => write!(f, " at instruction {at}, line {line}"),
+            (Some(at), None) => write!(f, " at instruction {at}"),
+            _ => Ok(())
+        }
+    }
+}
+
+impl std::error::Error for Error { }
+
+// the os' own code when there is one
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        let code = match (e.raw_os_error(), e.kind()) {
+            (Some(code), _) => code as Word,
+            (None, io::ErrorKind::Unsupported) => Error::UNSUPPORTED,
+            (None, io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData) => Error::INVALID,
+            (None, _) => Error::OTHER
+        };
+        Error::new(code, e.to_string())
+    }
+}
+
+// how many FN calls can be going at once, more than that traps with TOO_DEEP instead of
+// running out of stack
+pub const MAX_DEPTH: Word = 10_000;
+
+// everything a run of a program has to itself
+#[derive(Default)]
+struct Context {
+    files: Files,
+    // raised and not yet caught or reported
+    error: Option<Error>,
+    // what a program in checked memory mode may load from and store to, by start with the length
+    // and whether it came from malloc (offsets into `memory` for a sandboxed program)
+    allocations: BTreeMap<Word, (Word, bool)>,
+    // all a sandboxed program can get at, empty otherwise
+    memory: Vec<u8>,
+    // how many instructions the program can run and how many of those are left, the program
+    // takes them from `left` itself
+    fuel: Option<Box<(Word, Word)>>
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+// runs `f` with no open files and no error, whatever it leaves open is closed after,
+// the error is one the program didn't catch
+pub fn with_context<T>(f: impl FnOnce() -> T) -> (T, Option<Error>) {
+    struct Restore(Context);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CONTEXT.with(|c| mem::swap(&mut *c.borrow_mut(), &mut self.0));
+        }
+    }
+
+    let _restore = Restore(CONTEXT.with(|c| mem::take(&mut *c.borrow_mut())));
+    let out = f();
+    (out, CONTEXT.with(|c| c.borrow_mut().error.take()))
+}
+
+pub type RaiseFn = unsafe extern "C" fn(Word, *const u8, usize);
+
+// set when this copy of the runtime is part of a shared library module
+static FORWARD: OnceLock<RaiseFn> = OnceLock::new();
+
+// the error of a module function, the compiled program sees it once the function returns
+pub fn raise(error: Error) {
+    match FORWARD.get() {
+        Some(to) => unsafe { to(error.code, error.message.as_ptr(), error.message.len()) },
+        None => CONTEXT.with(|c| c.borrow_mut().error = Some(error))
+    }
+}
+
+// `len` bytes of utf8 at `message`
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn rba_raise(code: Word, message: *const u8, len: usize) {
+    let message = std::slice::from_raw_parts(message, len);
+    raise(Error::new(code, String::from_utf8_lossy(message)));
+}
+
+// what every module descriptor's `forward` is
+pub extern "C" fn rba_forward_errors(to: RaiseFn) {
+    let _ = FORWARD.set(to);
+}
+
+// 1 if the call that just returned raised an error, which is left for whoever runs the program
+#[no_mangle]
+pub extern "C" fn rba_failed() -> Word {
+    CONTEXT.with(|c| c.borrow().error.is_some()) as Word
+}
+
+// the code of the error the call that just returned raised, 0 if it didn't, the program deals with it
+// (running out of fuel is left alone, the program has to stop and rba_failed says so)
+#[no_mangle]
+pub extern "C" fn rba_take_error() -> Word {
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        match &c.error {
+            Some(e) if e.code == Error::OUT_OF_FUEL => 0,
+            _ => c.error.take().map_or(0, |e| e.code)
+        }
+    })
+}
+
+// the program gives up on the error it just got at instruction `at`, the first place it's given up is the one reported
+#[no_mangle]
+pub extern "C" fn rba_locate(at: Word) {
+    CONTEXT.with(|c| {
+        if let Some(e) = &mut c.borrow_mut().error {
+            e.at.get_or_insert(at as usize);
+        }
+    });
+}
+
+// a trap at instruction `at` nothing catches, `addr` is the address for BAD_ADDRESS
+#[no_mangle]
+pub extern "C" fn rba_trap(code: Word, at: Word, addr: Word) {
+    let message = match code {
+        Error::DIV_ZERO => String::from("division by zero"),
+        Error::BAD_ADDRESS => format!("{addr:#x} is not in an allocation"),
+        Error::OUT_OF_BOUNDS => format!("{addr:#x} is outside of the sandbox's memory"),
+        Error::DENIED => String::from("the sandbox doesn't allow calling this function"),
+        Error::TOO_DEEP => format!("calls went more than {MAX_DEPTH} deep"),
+        // `addr` is how many instructions ran since fuel was last taken
+        Error::OUT_OF_FUEL => {
+            let ran = CONTEXT.with(|c| c.borrow_mut().fuel.as_deref_mut().map_or(0, |(budget, left)| {
+                let ran = *budget - *left + addr;
+                *left = 0;
+                ran
+            }));
+            format!("fuel exhausted after {ran} instructions")
+        }
+        _ => format!("trap {code}")
+    };
+    raise(Error { at: Some(at as usize), ..Error::new(code, message) });
+}
+
+// checked memory mode, whether the `len` bytes at `addr` are all in one allocation
+#[no_mangle]
+pub extern "C" fn rba_check(addr: Word, len: Word) -> Word {
+    CONTEXT.with(|c| {
+        let c = c.borrow();
+        let inside = c.allocations.range(..=addr).next_back()
+            .is_some_and(|(start, (size, _))| addr.checked_add(len).is_some_and(|end| end <= start + size));
+        inside as Word
+    })
+}
+
+// checked memory mode, `len` bytes at `addr` the program didn't get from malloc (strings) can be used too
+#[no_mangle]
+pub extern "C" fn rba_allow(addr: Word, len: Word) {
+    CONTEXT.with(|c| c.borrow_mut().allocations.insert(addr, (len, false)));
+}
+
+// malloc and free for checked memory mode, keeping track of what's allocated
+#[no_mangle]
+pub extern "C" fn rba_malloc(size: Word) -> Word {
+    let ptr = unsafe { libc_malloc(size as usize) } as Word;
+    if ptr != 0 {
+        CONTEXT.with(|c| c.borrow_mut().allocations.insert(ptr, (size, true)));
+    }
+    ptr
+}
+
+#[no_mangle]
+pub extern "C" fn rba_free(ptr: Word) {
+    // anything else is a string, not the program's or freed already
+    let malloced = CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        let malloced = matches!(c.allocations.get(&ptr), Some((_, true)));
+        if malloced {
+            c.allocations.remove(&ptr);
+        }
+        malloced
+    });
+    if malloced {
+        unsafe { libc_free(ptr as *mut u8) };
+    }
+}
+
+// the run can go on for `budget` instructions, returns where what's left of it is kept
+#[no_mangle]
+pub extern "C" fn rba_refuel(budget: Word) -> Word {
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        let fuel = c.fuel.insert(Box::new((budget, budget)));
+        &mut fuel.1 as *mut Word as Word
+    })
+}
+
+// where what's left of the run's fuel is kept, 0 without any
+#[no_mangle]
+pub extern "C" fn rba_fuel() -> Word {
+    CONTEXT.with(|c| c.borrow_mut().fuel.as_deref_mut().map_or(0, |fuel| &mut fuel.1 as *mut Word as Word))
+}
+
+// the first bytes of a sandbox's memory, so 0 is never something malloc gives out
+pub const SANDBOX_NULL: Word = 8;
+
+// gives the run `size` bytes of memory starting out as the `len` bytes at `image` (the program's strings
+// after SANDBOX_NULL), returns where it is
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn rba_sandbox(size: Word, image: *const u8, len: Word) -> Word {
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        c.memory = vec![0; size as usize];
+        c.memory[..len as usize].copy_from_slice(std::slice::from_raw_parts(image, len as usize));
+        if len > SANDBOX_NULL {
+            c.allocations.insert(SANDBOX_NULL, (len - SANDBOX_NULL, false));
+        }
+        c.memory.as_mut_ptr() as Word
+    })
+}
+
+// where the sandbox's memory is, 0 without one
+#[no_mangle]
+pub extern "C" fn rba_memory() -> Word {
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        if c.memory.is_empty() { 0 } else { c.memory.as_mut_ptr() as Word }
+    })
+}
+
+// malloc and free for a sandboxed program, offsets into its memory from the first gap big enough
+#[no_mangle]
+pub extern "C" fn rba_sandbox_malloc(size: Word) -> Word {
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        let mut at = SANDBOX_NULL;
+        for (start, (len, _)) in &c.allocations {
+            if start - at >= size.max(1) {
+                break;
+            }
+            at = (start + len).next_multiple_of(8);
+        }
+
+        if at.checked_add(size.max(1)).is_some_and(|end| end <= c.memory.len() as Word) {
+            c.allocations.insert(at, (size.max(1), true));
+            at
+        } else {
+            0
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rba_sandbox_free(ptr: Word) {
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        if matches!(c.allocations.get(&ptr), Some((_, true))) {
+            c.allocations.remove(&ptr);
+        }
+    });
+}
+
+extern "C" {
+    #[link_name = "malloc"]
+    fn libc_malloc(size: usize) -> *mut u8;
+    #[link_name = "free"]
+    fn libc_free(ptr: *mut u8);
+}
+
+// what a module function can give back, the error of a Result is raised and the call gives back
+// `failed()` instead
+pub trait Returns {
+    type Output;
+    const RETURNS: bool;
+
+    fn output(self, function: &str) -> Self::Output;
+    fn failed() -> Self::Output;
+}
+
+impl Returns for Word {
+    type Output = Word;
+    const RETURNS: bool = true;
+
+    fn output(self, _: &str) -> Word { self }
+    fn failed() -> Word { ERR }
+}
+
+impl Returns for () {
+    type Output = ();
+    const RETURNS: bool = false;
+
+    fn output(self, _: &str) { }
+    fn failed() { }
+}
+
+impl Returns for Wp {
+    type Output = Wp;
+    const RETURNS: bool = true;
+
+    fn output(self, _: &str) -> Wp { self }
+    fn failed() -> Wp { Wp(ERR) }
+}
+
+impl<T: Returns, E: Into<Error>> Returns for Result<T, E> {
+    type Output = T::Output;
+    const RETURNS: bool = T::RETURNS;
+
+    fn output(self, function: &str) -> T::Output {
+        match self {
+            Ok(val) => val.output(function),
+            Err(e) => {
+                let e = e.into();
+                raise(Error { message: format!("{function}: {}", e.message), ..e });
+                T::failed()
+            }
+        }
+    }
+
+    fn failed() -> T::Output { T::failed() }
+}
+
+// used by OUT
+#[no_mangle]
+pub extern "C" fn rba_printc(val: Word) {
+    if let Err(e) = streams(|s| writeln!(s.stdout, "{val}")) {
+        raise(Error { message: format!("OUT: {e}"), ..e.into() });
+    }
+}
+
+// an ahead of time compiled main calls this before returning, nothing flushes stdout
+// or closes files after that, gives back the exit status
+#[no_mangle]
+pub extern "C" fn rba_finish() -> i32 {
+    let _ = streams(|s| s.stdout.flush());
+    let context = CONTEXT.with(|c| mem::take(&mut *c.borrow_mut()));
+    match context.error {
+        Some(e) => {
+            eprintln!("error: {e}");
+            1
+        }
+        None => 0
+    }
+}
+
+pub struct Std;
+
+#[module(std)]
+impl Std {
+    fn printc(val: Word) -> Result<(), Error> {
+        streams(|s| writeln!(s.stdout, "{val}")).map_err(Error::from)
+    }
+    fn printa(val: Word) -> Result<(), Error> {
+        let c = char::from_u32(val as u32).ok_or_else(|| Error::new(Error::INVALID, format!("{val} is not a character")))?;
+        streams(|s| write!(s.stdout, "{c}")).map_err(Error::from)
+    }
+    fn top_8(val: Word) -> Word { val >> 56 }
+    fn addr_8(val: Addr) -> Word {
+        unsafe {
+            let ptr = val as *mut u8;
+            ptr.read() as Word
+        }
+    }
+}
+
+// what io functions give back when something goes wrong, -1 to a program
+pub const ERR: Word = Word::MAX;
+
+enum Open {
+    File(File),
+    Dir(ReadDir)
+}
+
+// io's open files and directories, a handle is the slot's generation in the high half and its index
+// (after stdout, stderr and stdin) in the low half, so a closed or made up handle never finds anything
+#[derive(Default)]
+pub struct Files {
+    slots: Vec<(u32, Option<Open>)>,
+    free: Vec<usize>
+}
+
+impl Files {
+    fn open(&mut self, open: Open) -> Word {
+        let idx = self.free.pop().unwrap_or_else(|| {
+            self.slots.push((0, None));
+            self.slots.len() - 1
+        });
+
+        let slot = &mut self.slots[idx];
+        slot.0 += 1;
+        slot.1 = Some(open);
+        ((slot.0 as Word) << 32) | (idx as Word + 3)
+    }
+
+    fn slot(&mut self, handle: Word) -> Result<&mut (u32, Option<Open>), Error> {
+        let idx = (handle as u32 as usize).wrapping_sub(3);
+        match self.slots.get_mut(idx) {
+            Some(slot) if slot.0 as Word == handle >> 32 && slot.1.is_some() => Ok(slot),
+            _ => Err(Error::new(Error::BAD_HANDLE, format!("{handle} is not an open file")))
+        }
+    }
+
+    fn get(&mut self, handle: Word) -> Result<&mut File, Error> {
+        match self.slot(handle)?.1.as_mut() {
+            Some(Open::File(file)) => Ok(file),
+            _ => Err(Error::new(Error::BAD_HANDLE, format!("{handle} is a directory, not a file")))
+        }
+    }
+
+    fn dir(&mut self, handle: Word) -> Result<&mut ReadDir, Error> {
+        match self.slot(handle)?.1.as_mut() {
+            Some(Open::Dir(dir)) => Ok(dir),
+            _ => Err(Error::new(Error::BAD_HANDLE, format!("{handle} is a file, not a directory")))
+        }
+    }
+
+    fn close(&mut self, handle: Word) -> Result<(), Error> {
+        self.slot(handle)?.1 = None;
+        self.free.push(handle as u32 as usize - 3);
+        Ok(())
+    }
+}
+
+fn files<T>(f: impl FnOnce(&mut Files) -> Result<T, Error>) -> Result<T, Error> {
+    CONTEXT.with(|c| f(&mut c.borrow_mut().files))
+}
+
+fn unsupported(what: &str) -> Error {
+    Error::new(Error::UNSUPPORTED, what)
+}
+
+#[repr(transparent)]
+struct Wp(Word);
+
+impl Wp {
+    fn write<T>(&self, f: impl FnOnce(&mut dyn Write) -> io::Result<T>) -> Result<T, Error> {
+        match self.0 {
+            0 => streams(|s| f(&mut s.stdout)).map_err(Error::from),
+            1 => streams(|s| f(&mut s.stderr)).map_err(Error::from),
+            2 => Err(unsupported("stdin can't be written to")),
+            n => files(|files| Ok(f(files.get(n)?)?))
+        }
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&mut dyn Read) -> io::Result<T>) -> Result<T, Error> {
+        match self.0 {
+            0 | 1 => Err(unsupported("stdout and stderr can't be read from")),
+            2 => streams(|s| f(&mut s.stdin)).map_err(Error::from),
+            n => files(|files| Ok(f(files.get(n)?)?))
+        }
+    }
+}
+
+fn handle(open: Open) -> Result<Wp, Error> {
+    files(|f| Ok(Wp(f.open(open))))
+}
+
+// `num` bytes of utf8 at `ptr`
+unsafe fn path(ptr: Addr, num: Word) -> String {
+    let ptr = ptr as *const u8;
+    let slice = std::slice::from_raw_parts(ptr, num as usize);
+    String::from_utf8_lossy(slice).into_owned()
+}
+
+unsafe fn open(name: Addr, num: Word, options: &OpenOptions) -> Result<Wp, Error> {
+    handle(Open::File(options.open(path(name, num))?))
+}
+
+// the os side of handles, a raw descriptor is a file descriptor on unix and a HANDLE on windows
+#[cfg(unix)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::{FromRawFd, RawFd};
+    use crate::Word;
+
+    // rba's handle for the process' own stdin, stdout or stderr
+    pub fn standard(raw: Word) -> Option<Word> {
+        match raw {
+            0 => Some(2),
+            1 => Some(0),
+            2 => Some(1),
+            _ => None
+        }
+    }
+
+    // a copy of `raw` the handle table can close without closing the original
+    pub fn duplicate(raw: Word) -> io::Result<File> {
+        let fd = RawFd::try_from(raw).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        // stays clear of 0, 1 and 2 so they always mean the process' own
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+        if copy < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(unsafe { File::from_raw_fd(copy) })
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::{AsRawHandle, BorrowedHandle, RawHandle};
+    use crate::Word;
+
+    pub fn standard(raw: Word) -> Option<Word> {
+        let is = |handle: RawHandle| handle as usize as Word == raw;
+        if is(io::stdin().as_raw_handle()) {
+            Some(2)
+        } else if is(io::stdout().as_raw_handle()) {
+            Some(0)
+        } else if is(io::stderr().as_raw_handle()) {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    pub fn duplicate(raw: Word) -> io::Result<File> {
+        let handle = unsafe { BorrowedHandle::borrow_raw(raw as usize as RawHandle) };
+        handle.try_clone_to_owned().map(File::from)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use crate::Word;
+
+    pub fn standard(_: Word) -> Option<Word> {
+        None
+    }
+
+    pub fn duplicate(_: Word) -> io::Result<File> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+pub struct IO;
+
+// every function raises an error for a handle that isn't stdout, stderr, stdin or an open file
+// (or directory for dir_next), and for anything the os refuses to do, the call gives back ERR
+#[module(io)]
+impl IO {
+    fn stdout() -> Word { 0 }
+    fn stderr() -> Word { 1 }
+    fn stdin() -> Word { 2 }
+
+    // how many bytes were written
+    fn write(handle: Wp, data: Addr, num: Len) -> Result<Word, Error> {
+        let slice = unsafe { std::slice::from_raw_parts(data as *const u8, num as usize) };
+        handle.write(|w| w.write_all(slice))?;
+        Ok(num)
+    }
+
+    fn read(handle: Wp, into: Addr, max: Len) -> Result<Word, Error> {
+        let slice = unsafe { std::slice::from_raw_parts_mut(into as *mut u8, max as usize) };
+        handle.read(|r| r.read(slice)).map(|n| n as Word)
+    }
+
+    // creates the file, or empties it if it's already there
+    fn open_file(name: Addr, num: Len) -> Result<Wp, Error> {
+        unsafe { open(name, num, OpenOptions::new().write(true).create(true).truncate(true)) }
+    }
+
+    fn open_read(name: Addr, num: Len) -> Result<Wp, Error> {
+        unsafe { open(name, num, OpenOptions::new().read(true)) }
+    }
+
+    // writes go to the end, the file is created if it isn't there
+    fn open_append(name: Addr, num: Len) -> Result<Wp, Error> {
+        unsafe { open(name, num, OpenOptions::new().append(true).create(true)) }
+    }
+
+    // reads and writes without emptying it first, the file is created if it isn't there
+    fn open_rw(name: Addr, num: Len) -> Result<Wp, Error> {
+        unsafe { open(name, num, OpenOptions::new().read(true).write(true).create(true)) }
+    }
+
+    // a file descriptor (a HANDLE on windows) the process was started with, pipes and sockets
+    // work like files, the process' own stdin, stdout and stderr give back 2, 0 and 1
+    fn open_fd(raw: Word) -> Result<Wp, Error> {
+        match platform::standard(raw) {
+            Some(std) => Ok(Wp(std)),
+            None => handle(Open::File(platform::duplicate(raw)?))
+        }
+    }
+
+    // 0 once closed, files and directories alike
+    fn close_file(file: Wp) -> Result<Word, Error> {
+        files(|f| f.close(file.0)).map(|_| 0)
+    }
+
+    // `offset` is signed, from the start (whence 0), the current position (1) or the end (2),
+    // gives back the new position
+    fn seek(handle: Wp, offset: Word, whence: Word) -> Result<Word, Error> {
+        let from = match whence {
+            0 => SeekFrom::Start(offset),
+            1 => SeekFrom::Current(offset as i64),
+            2 => SeekFrom::End(offset as i64),
+            _ => return Err(Error::new(Error::INVALID, format!("whence is 0, 1 or 2, not {whence}")))
+        };
+
+        files(|f| Ok(f.get(handle.0)?.seek(from)?))
+    }
+
+    fn tell(handle: Wp) -> Result<Word, Error> {
+        files(|f| Ok(f.get(handle.0)?.stream_position()?))
+    }
+
+    fn file_size(handle: Wp) -> Result<Word, Error> {
+        files(|f| Ok(f.get(handle.0)?.metadata()?.len()))
+    }
+
+    fn remove_file(name: Addr, num: Len) -> Result<Word, Error> {
+        unsafe { fs::remove_file(path(name, num))? };
+        Ok(0)
+    }
+
+    fn rename(from: Addr, from_num: Len, to: Addr, to_num: Len) -> Result<Word, Error> {
+        unsafe { fs::rename(path(from, from_num), path(to, to_num))? };
+        Ok(0)
+    }
+
+    fn mkdir(name: Addr, num: Len) -> Result<Word, Error> {
+        unsafe { fs::create_dir(path(name, num))? };
+        Ok(0)
+    }
+
+    // a handle for dir_next, closed with close_file
+    fn dir_open(name: Addr, num: Len) -> Result<Wp, Error> {
+        handle(Open::Dir(unsafe { fs::read_dir(path(name, num))? }))
+    }
+
+    // puts the name of the next entry into `into`, at most `max` bytes of it, and gives back
+    // how long the whole name is, 0 once there are no more
+    fn dir_next(dir: Wp, into: Addr, max: Len) -> Result<Word, Error> {
+        let entry = match files(|f| Ok(f.dir(dir.0)?.next().transpose()?))? {
+            Some(entry) => entry,
+            None => return Ok(0)
+        };
+
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        unsafe {
+            let ptr = into as *mut u8;
+            let len = name.len().min(max as usize);
+            ptr::copy_nonoverlapping(name.as_ptr(), ptr, len);
+        }
+        Ok(name.len() as Word)
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, io};
+use cranelift::prelude::*;
+use cranelift_module::{default_libcall_names, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use crate::asm::{self, AsmIns, Linking};
+use crate::modules::ModuleProvider;
+use crate::program::Options;
+
+// what the runtime's staticlib needs from the system, from `--print native-static-libs`
+#[cfg(target_os = "linux")]
+const NATIVE_LIBS: &[&str] = &["-lgcc_s", "-lutil", "-lrt", "-lpthread", "-lm", "-ldl", "-lc"];
+#[cfg(target_os = "macos")]
+const NATIVE_LIBS: &[&str] = &["-lSystem", "-lc", "-lm"];
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const NATIVE_LIBS: &[&str] = &[];
+
+// compiles the program into a relocatable object with a C `main`, module functions
+// are imported as rba_<module>_<function> and come from the runtime library
+pub(crate) fn into_object(ins: &[AsmIns], provider: impl ModuleProvider, options: &Options) -> Vec<u8> {
+    let builder = ObjectBuilder::new(asm::isa(), "rba", default_libcall_names()).unwrap();
+    let mut module = ObjectModule::new(builder);
+
+    let mut linking = Linking {
+        finish: Some(String::from("rba_finish")),
+        checked: options.checked,
+        sandbox: options.sandbox.clone(),
+        fuel: options.fuel,
+        ..Linking::default()
+    };
+    linking.symbols.insert(String::from("printc"), String::from("rba_printc"));
+    for (name, symbol, _) in options.allocator() {
+        linking.symbols.insert(name.to_string(), symbol.to_string());
+    }
+
+    for i in ins {
+        if let AsmIns::Include(lib) = i {
+            // looking at its signatures would load it, check has already reported it
+            if options.sandbox.as_ref().is_some_and(|s| !s.includes(lib, &provider)) {
+                continue;
+            }
+            for (name, sig) in provider.signatures(lib).unwrap_or_default() {
+                linking.symbols.insert(name.clone(), format!("rba_{lib}_{name}"));
+                linking.signatures.insert(name, sig);
+            }
+        }
+    }
+
+    let mut sig_main = module.make_signature();
+    sig_main.returns.push(AbiParam::new(types::I32));
+    asm::compile(&mut module, ins, sig_main, &mut linking);
+
+    module.finish().emit().unwrap()
+}
+
+// links an object from `into_object` into an executable using $CC (or cc)
+pub fn link(object: &Path, out: &Path) -> io::Result<()> {
+    let runtime = runtime_lib().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "can't find the rba runtime library, set RBA_RUNTIME to its path")
+    })?;
+
+    let cc = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    let status = Command::new(&cc)
+        .arg(object)
+        .arg(&runtime)
+        .args(NATIVE_LIBS)
+        .arg("-o")
+        .arg(out)
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{cc} failed ({status})")))
+    }
+}
+
+// $RBA_RUNTIME, otherwise next to the rba binary, which is where cargo leaves it
+fn runtime_lib() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("RBA_RUNTIME") {
+        return Some(path.into());
+    }
+
+    let name = if cfg!(windows) { "rba_runtime.lib" } else { "librba_runtime.a" };
+    let path = env::current_exe().ok()?.parent()?.join(name);
+    path.exists().then_some(path)
+}
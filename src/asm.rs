@@ -1,527 +1,1164 @@
-use std::collections::HashMap;
-use std::ops::Deref;
-use codegen::ir::UserFuncName;
-use cranelift::prelude::*;
-use cranelift_codegen::Context;
-use cranelift_codegen::data_value::DataValue;
-use cranelift_codegen::gimli::ReaderOffset;
-use cranelift_codegen::ir::{DynamicStackSlotData, DynamicType, StackSlot};
-use cranelift_codegen::settings::{self, Configurable};
-use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
-use crate::modules::{DefaultModuleProvider, Module as AModule, ModuleProvider};
-
-const PTR_LEN: usize = 8;
-const N_TYPE: Type = types::I64;
-
-pub(crate) type Word = u64;
-pub(crate) type Addr = Word;
-type Label = String;
-
-#[derive(Clone, Debug)]
-#[repr(u8)]
-pub enum Var {
-    Named(Label),
-    Addr(Box<Val>)
-}
-
-#[derive(Clone, Debug)]
-#[repr(u8)]
-pub enum Val {
-    Var(Var),
-    Const(Const)
-}
-
-#[derive(Clone, Debug)]
-#[repr(u8)]
-pub enum Const {
-    Word(Word),
-    Str(*const u8)
-}
-
-#[derive(Clone, Debug)]
-#[repr(u8)]
-pub enum AsmIns {
-    Include(Label),
-    Move(Val, Var),
-    Swap(Var, Var),
-    Add(Var, Val),
-    Sub(Var, Val),
-    Mul(Var, Val),
-    Div(Var, Val),
-    Mod(Var, Val),
-    Label(Label),
-    JZ(Val, Label),
-    JNz(Val, Label),
-    TakeInput,
-    CopyInput,
-    Nop,
-    Output(Val),
-    Call(Label, Vec<Val>, Option<Var>),
-    Function(Label, Vec<AsmIns>)
-}
-
-use cranelift_codegen::isa::{Builder, LookupError, OwnedTargetIsa};
-use libc::{c_char, c_int, size_t};
-use pom::Parser;
-
-fn printc(val: Word) { println!("{val}") }
-fn print_ascii(val: Word) { print!("{}", char::from_u32(val as u32).unwrap()) }
-fn top_8(val: Word) -> Word { val >> 56 }
-
-// uses cranelift to generate x86 asm, faster than interpreting this processors instructions
-pub fn into_cr<M: ModuleProvider>(ins: &[AsmIns], provider: M) -> unsafe extern "C" fn() {
-    let mut flag_builder = settings::builder();
-    flag_builder.set("use_colocated_libcalls", "false").unwrap();
-    // FIXME set back to true once the x64 backend supports it.
-    flag_builder.set("is_pic", "true").unwrap();
-    flag_builder.set("opt_level", "speed").unwrap();
-    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
-        panic!("host machine is not supported: {}", msg);
-    });
-
-    let flags = settings::Flags::new(flag_builder);
-    let isa = isa_builder
-        .finish(flags)
-        .unwrap();
-
-    let mut builder = JITBuilder::with_isa(isa.clone(), default_libcall_names());
-    let printc_addr = printc as *const u8;
-    builder.symbol("printc", printc_addr);
-
-    for i in ins {
-        match i {
-            AsmIns::Include(lib) => {
-                provider.add_functions(&mut builder, lib);
-            }
-            _ => { }
-        }
-    }
-
-    let mut module = JITModule::new(builder);
-
-    let mut ctx = module.make_context();
-    let mut func_ctx = FunctionBuilderContext::new();
-
-    // every function has its own Memory space
-    // Determine arg strategy soon, maybe arg1 arg2 arg3?
-    fn make_function(name: &str, signature: Signature,
-                     module: &mut JITModule, ctx: &mut Context, func_ctx: &mut FunctionBuilderContext, ins: &[AsmIns]) -> FuncId {
-        let func_s = module
-            .declare_function(name, Linkage::Export, &signature)
-            .unwrap();
-
-        let mut sig = module.make_signature();
-        sig.params.push(AbiParam::new(N_TYPE));
-
-        let callee = module
-            .declare_function("printc", Linkage::Import, &sig)
-            .map_err(|e| e.to_string()).unwrap();
-
-        ctx.func.signature = signature;
-        ctx.func.name = UserFuncName::user(0, func_s.as_u32());
-
-        let local_callee = module
-            .declare_func_in_func(callee, &mut ctx.func);
-
-        let mut function_lookup = HashMap::new();
-
-        for i in ins {
-            match i {
-                AsmIns::Call(label, params, out) => {
-                    if function_lookup.contains_key(label) { continue; }
-
-                    let mut sig = module.make_signature();
-                    for _ in 0..params.len() {
-                        sig.params.push(AbiParam::new(N_TYPE));
-                    }
-                    if out.is_some() { sig.returns.push(AbiParam::new(N_TYPE)); }
-
-                    let callee = module
-                        .declare_function(label, Linkage::Import, &sig)
-                        .map_err(|e| e.to_string()).unwrap();
-
-                    let func_ref = module.declare_func_in_func(callee, &mut ctx.func);
-
-                    function_lookup.insert(label, func_ref);
-                }
-                _ => { }
-            }
-        }
-
-        let mut bcx = FunctionBuilder::new(&mut ctx.func, func_ctx);
-        let block = bcx.create_block();
-        bcx.switch_to_block(block);
-
-        let mut vidx = 0;
-        let mut variable_lookup = HashMap::new();
-        let mut block_lookup = HashMap::new();
-
-        struct Env {
-            vl: HashMap<Label, Variable>,
-            vi: usize
-        }
-
-        let mut env = Env {
-            vl: variable_lookup,
-            vi: vidx
-        };
-
-        fn get_val1(v: Val, bcx: &mut FunctionBuilder, env: &mut Env) -> Value {
-            match v {
-                Val::Var(v) => { get_var1(v, bcx, env) }
-                Val::Const(Const::Word(word)) => {
-                    bcx.ins().iconst(N_TYPE, word as i64)
-                }
-                Val::Const(Const::Str(string)) => {
-                    bcx.ins().iconst(N_TYPE, string as i64)
-                }
-            }
-        }
-
-        fn get_var1(v: Var, bcx: &mut FunctionBuilder, env: &mut Env) -> Value {
-            match v {
-                Var::Named(label) => {
-                    if let Some(v) = env.vl.get(&label) {
-                        bcx.use_var(v.clone())
-                    } else {
-                        let v = Variable::new(env.vi);
-                        env.vi += 1;
-
-                        env.vl.insert(label, v.clone());
-                        bcx.declare_var(v, N_TYPE);
-                        bcx.use_var(v)
-                    }
-                }
-                Var::Addr(bval) => {
-                    let addr = get_val1(*bval, bcx, env);
-                    bcx.ins().load(N_TYPE, MemFlags::new(), addr, 0)
-                }
-            }
-        }
-
-        fn set_var1(var: Var, to: Value,  bcx: &mut FunctionBuilder, env: &mut Env) {
-            match var {
-                Var::Named(label) => {
-                    if let Some(v) = env.vl.get(&label) {
-                        bcx.def_var(v.clone(), to)
-                    } else {
-                        let v = Variable::new(env.vi);
-                        env.vi += 1;
-
-                        env.vl.insert(label, v.clone());
-                        bcx.declare_var(v, N_TYPE);
-                        bcx.def_var(v, to)
-                    }
-                }
-                Var::Addr(bval) => {
-                    let addr = get_val1(*bval, bcx, env);
-                    bcx.ins().store(MemFlags::new(), to, addr, 0);
-                }
-            }
-        }
-
-        let mut get_value = |r: &Val, bcx: &mut FunctionBuilder, env: &mut Env| { get_val1(r.clone(), bcx, env) };
-        let mut get_var = |v: &Var, bcx: &mut FunctionBuilder, env: &mut Env| { get_var1(v.clone(), bcx, env) };
-        let mut set_var = |v: &Var, val: Value, bcx: &mut FunctionBuilder, env: &mut Env| { set_var1(v.clone(), val, bcx, env) };
-
-        for i in ins {
-            match i {
-                AsmIns::Label(id) => {
-                    let bl = bcx.create_block();
-                    block_lookup.insert(id, bl);
-                }
-                _ => { }
-            }
-        }
-
-        for i in ins {
-            match i {
-                AsmIns::Move(val, var) => {
-                    let val = get_value(val, &mut bcx, &mut env);
-                    set_var(var, val, &mut bcx, &mut env);
-                }
-                AsmIns::Swap(vr1, vr2) => {
-                    let r1 = get_var(vr1, &mut bcx, &mut env);
-                    let r2 = get_var(vr2, &mut bcx, &mut env);
-
-                    set_var(vr2, r1, &mut bcx, &mut env);
-                    set_var(vr1, r2, &mut bcx, &mut env);
-                }
-                AsmIns::Add(var, val) => {
-                    let v1 = get_var(var, &mut bcx, &mut env);
-                    let v2 = get_value(val, &mut bcx, &mut env);
-
-                    let v3 = bcx.ins().iadd(v1, v2);
-                    set_var(var, v3, &mut bcx, &mut env);
-                }
-                AsmIns::Sub(var, val) => {
-                    let v1 = get_var(var, &mut bcx, &mut env);
-                    let v2 = get_value(val, &mut bcx, &mut env);
-
-                    let v3 = bcx.ins().isub(v1, v2);
-                    set_var(var, v3, &mut bcx, &mut env);
-                }
-                AsmIns::Mul(var, val) => {
-                    let v1 = get_var(var, &mut bcx, &mut env);
-                    let v2 = get_value(val, &mut bcx, &mut env);
-
-                    let v3 = bcx.ins().imul(v1, v2);
-                    set_var(var, v3, &mut bcx, &mut env);
-                }
-                AsmIns::Div(var, val) => {
-                    let v1 = get_var(var, &mut bcx, &mut env);
-                    let v2 = get_value(val, &mut bcx, &mut env);
-
-                    let v3 = bcx.ins().udiv(v1, v2);
-                    set_var(var, v3, &mut bcx, &mut env);
-                }
-                AsmIns::Mod(var, val) => {
-                    let v1 = get_var(var, &mut bcx, &mut env);
-                    let v2 = get_value(val, &mut bcx, &mut env);
-
-                    let v3 = bcx.ins().urem(v1, v2);
-                    set_var(var, v3, &mut bcx, &mut env);
-                }
-                AsmIns::JZ(val, addr) => {
-                    let bl = block_lookup.get(addr).unwrap().clone();
-                    let eb = bcx.create_block();
-
-                    let bool = get_value(val, &mut bcx, &mut env);
-                    bcx.ins().brif(bool, eb, &[], bl, &[]);
-                    bcx.switch_to_block(eb);
-                    // bcx.seal_block(eb);
-                }
-                AsmIns::JNz(val, addr) => {
-                    let bl = block_lookup.get(&addr).unwrap().clone();
-                    let eb = bcx.create_block();
-
-                    let bool = get_value(val, &mut bcx, &mut env);
-                    bcx.ins().brif(bool, bl, &[], eb, &[]);
-                    bcx.switch_to_block(eb);
-                    // bcx.seal_block(eb);
-                }
-                AsmIns::Label(id) => {
-                    let bl = block_lookup.get(&id).unwrap().clone();
-                    bcx.ins().jump(bl, &[]);
-
-                    bcx.insert_block_after(bl, bcx.current_block().unwrap());
-                    bcx.switch_to_block(bl);
-                }
-                AsmIns::Output(val) => {
-                    let val= get_value(val, &mut bcx, &mut env);
-                    bcx.ins().call(local_callee, &[val]);
-                }
-                AsmIns::Call(label, params, ret) => {
-                    let args: Vec<Value> = params.iter().map(|arg| get_value(arg, &mut bcx, &mut env)).collect();
-
-                    let inst = bcx.ins().call(function_lookup.get(label).unwrap().clone(), &args);
-
-                    if let Some(ret) = ret {
-                        let out = bcx.inst_results(inst)[0];
-
-                        set_var(ret, out, &mut bcx, &mut env);
-                    }
-                }
-                _ => { }
-            }
-        }
-
-        bcx.ins().return_(&[]);
-        bcx.seal_all_blocks();
-        bcx.finalize();
-
-        func_s
-    }
-
-    let sig_main = module.make_signature();
-    let func_main = make_function("main", sig_main, &mut module, &mut ctx, &mut func_ctx, ins);
-
-    module.define_function(func_main, &mut ctx).unwrap();
-    module.clear_context(&mut ctx);
-
-    module.finalize_definitions().unwrap();
-
-
-    let code_main = module.get_finalized_function(func_main);
-    let ret = unsafe { std::mem::transmute::<_, unsafe extern "C" fn()>(code_main) };
-
-    ret
-}
-
-pub unsafe fn execute(ins: &[AsmIns], provider: impl ModuleProvider) {
-    let mut regs = HashMap::new();
-
-    let mut lookup = HashMap::new();
-    let mut func = HashMap::new();
-    func.insert(String::from("malloc"), libc::malloc as *const u8);
-    func.insert(String::from("atol"), libc::atol as *const u8);
-
-    for (idx, i) in ins.iter().enumerate() {
-        match i {
-            AsmIns::Label(addr) => { lookup.insert(addr, idx + 1); }
-            AsmIns::Include(lbl) => {
-                provider.get_ptrs(&mut func, lbl);
-            }
-            _ => { }
-        }
-    }
-
-    let mut idx = 0;
-    loop {
-        if idx >= ins.len() { break; }
-        let ir = run_ins(&ins[idx], &mut regs, &func);
-
-        match ir {
-            InsResult::Rewind(pos) => { idx = *lookup.get(&pos).expect("Invalid jump"); }
-            _ => { idx += 1; }
-        }
-    }
-}
-
-#[derive(Clone, Eq, PartialEq, Hash)]
-enum InsResult {
-    Failure,
-    Success,
-    Rewind(Label)
-}
-
-unsafe fn run_ins(ins: &AsmIns, rgs: &mut HashMap<String, Word>, funcs: &HashMap<String, *const u8>) -> InsResult {
-    unsafe fn get_val1(v: Val, rgs: &mut HashMap<String, Word>) -> Word {
-        match v {
-            Val::Var(v) => { get_var1(v, rgs) }
-            Val::Const(Const::Word(w)) => { w }
-            Val::Const(Const::Str(ptr)) => { ptr as Word }
-        }
-    }
-
-    unsafe fn get_var1(v: Var, rgs: &mut HashMap<String, Word>) -> Word {
-        match v {
-            Var::Named(lbl) => {
-                if let Some(w) = rgs.get(&lbl) {
-                    *w
-                } else {
-                    0
-                }
-            },
-            Var::Addr(bval) => {
-                let addr = get_val1(*bval, rgs) as usize;
-
-                // wildly unsafe, but language specification demands it
-                let u64 = unsafe { *std::mem::transmute::<usize, *const u64>(addr) };
-                u64
-            }
-        }
-    }
-
-    unsafe fn set_var1(v: Var, to: Word, rgs: &mut HashMap<String, Word>) {
-        match v {
-            Var::Named(lbl) => {
-                if let Some(w) = rgs.get_mut(&lbl) {
-                    *w = to;
-                } else {
-                    rgs.insert(lbl, to);
-                }
-            }
-            Var::Addr(bval) => {
-                let addr = get_val1(*bval, rgs);
-                let addr = addr as usize;
-                
-                // wildly unsafe, but language specification demands it
-                let u64 = unsafe { std::mem::transmute::<usize, *mut u64>(addr) };
-                
-                unsafe {
-                    *u64 = to;
-                }
-            }
-        }
-    }
-
-    let get_val = |v: &Val, rgs: &mut HashMap<String, Word>| {
-        get_val1(v.clone(), rgs)
-    };
-
-    let get_var = |v: &Var, rgs: &mut HashMap<String, Word>| {
-        get_var1(v.clone(), rgs)
-    };
-
-    let mut set_var = |v: &Var, val: Word, rgs: &mut HashMap<String, Word>| {
-        set_var1(v.clone(), val, rgs);
-    };
-
-    match ins {
-        AsmIns::Move(val, var) => {
-            let v = get_val(val, rgs);
-            set_var(var, v, rgs);
-        }
-        AsmIns::Swap(v1, v2) => {
-            let r1 = get_var(v1, rgs);
-            let r2 = get_var(v2, rgs);
-
-            set_var(v2, r1, rgs);
-            set_var(v1, r2, rgs);
-        }
-        AsmIns::Add(var, val) => {
-            let a = get_var(var, rgs);
-            let b = get_val(val, rgs);
-            set_var(var, a + b, rgs);
-        }
-        AsmIns::Sub(var, val) => {
-            let a = get_var(var, rgs);
-            let b = get_val(val, rgs);
-            set_var(var, a - b, rgs);
-        }
-        AsmIns::Mul(var, val) => {
-            let a = get_var(var, rgs);
-            let b = get_val(val, rgs);
-            set_var(var, a * b, rgs);
-        }
-        AsmIns::Div(var, val) => {
-            let a = get_var(var, rgs);
-            let b = get_val(val, rgs);
-            set_var(var, a / b, rgs);
-        }
-        AsmIns::Mod(var, val) => {
-            let a = get_var(var, rgs);
-            let b = get_val(val, rgs);
-            set_var(var, a % b, rgs);
-        }
-        AsmIns::JZ(val, addr) => {
-            let val = get_val(val, rgs);
-            if val == 0 { return InsResult::Rewind(addr.clone()); }
-        }
-        AsmIns::JNz(val, addr) => {
-            let val = get_val(val, rgs);
-            if val != 0 { return InsResult::Rewind(addr.clone()); }
-        }
-        AsmIns::Output(val) => {
-            let v = get_val(val, rgs);
-            println!("{v}");
-        }
-        AsmIns::Call(lbl, params, out) => {
-            let ptr = funcs.get(lbl);
-
-            // same thing with this match mess, someday this will be fixed :(
-            if let Some(ptr) = ptr {
-                let ptr = *ptr;
-                match (params.len(), out.is_some()) {
-                    (0, true) => { set_var(out.as_ref().unwrap(), std::mem::transmute::<_, fn() -> Word>(ptr)(), rgs); }
-                    (1, true) => { set_var(out.as_ref().unwrap(), std::mem::transmute::<_, fn(Word) -> Word>(ptr)(get_val(&params[0], rgs)), rgs); }
-                    (2, true) => { set_var(out.as_ref().unwrap(), std::mem::transmute::<_, fn(Word, Word) -> Word>
-                        (ptr)(get_val(&params[0], rgs), get_val(&params[1], rgs)), rgs); }
-                    (3, true) => { set_var(out.as_ref().unwrap(), std::mem::transmute::<_, fn(Word, Word, Word) -> Word>
-                        (ptr)(get_val(&params[0], rgs), get_val(&params[1], rgs), get_val(&params[2], rgs)), rgs); }
-                    (0, false) => { std::mem::transmute::<_, fn()>(ptr)() }
-                    (1, false) => { std::mem::transmute::<_, fn(Word)>(ptr)(get_val(&params[0], rgs)) }
-                    (2, false) => { std::mem::transmute::<_, fn(Word, Word)>(ptr)(get_val(&params[0], rgs), get_val(&params[1], rgs)) }
-                    (3, false) => { std::mem::transmute::<_, fn(Word, Word, Word)>(ptr)
-                        (get_val(&params[0], rgs), get_val(&params[1], rgs), get_val(&params[2], rgs)) }
-                    _ => {}
-                }
-            }
-        }
-        _ => {}
-    }
-
-    InsResult::Success
-}
\ No newline at end of file
+use std::collections::HashMap;
+use std::sync::Arc;
+use codegen::ir::UserFuncName;
+use cranelift::prelude::*;
+use cranelift_codegen::Context;
+use cranelift_codegen::ir::{FuncRef, GlobalValue};
+use cranelift_codegen::isa::OwnedTargetIsa;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, DataContext, DataId, FuncId, Linkage, Module};
+use crate::modules::{FnSig, HostFn, Kind};
+use crate::program::Sandbox;
+use rba_runtime::{Error, MAX_DEPTH};
+
+const PTR_LEN: usize = 8;
+const N_TYPE: Type = types::I64;
+
+pub type Word = u64;
+type Label = String;
+
+#[derive(Clone, Debug)]
+#[repr(u8)]
+pub enum Var {
+    Named(Label),
+    Addr(Box<Val>, Width)
+}
+
+// how much memory an address is read/written as, smaller loads are zero extended and stores truncated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    W8,
+    W16,
+    W32,
+    W64
+}
+
+impl Width {
+    pub fn bytes(self) -> Word {
+        match self {
+            Width::W8 => 1,
+            Width::W16 => 2,
+            Width::W32 => 4,
+            Width::W64 => 8
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[repr(u8)]
+pub enum Val {
+    Var(Var),
+    Const(Const)
+}
+
+#[derive(Clone, Debug)]
+#[repr(u8)]
+pub enum Const {
+    Word(Word),
+    // the backends put a nul after it in memory
+    Str(Arc<str>)
+}
+
+// how JCmp compares its two values, the S variants treat them as two's complement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    SLt,
+    SLe,
+    SGt,
+    SGe
+}
+
+impl Cmp {
+    fn int_cc(self) -> IntCC {
+        match self {
+            Cmp::Eq => IntCC::Equal,
+            Cmp::Ne => IntCC::NotEqual,
+            Cmp::Lt => IntCC::UnsignedLessThan,
+            Cmp::Le => IntCC::UnsignedLessThanOrEqual,
+            Cmp::Gt => IntCC::UnsignedGreaterThan,
+            Cmp::Ge => IntCC::UnsignedGreaterThanOrEqual,
+            Cmp::SLt => IntCC::SignedLessThan,
+            Cmp::SLe => IntCC::SignedLessThanOrEqual,
+            Cmp::SGt => IntCC::SignedGreaterThan,
+            Cmp::SGe => IntCC::SignedGreaterThanOrEqual
+        }
+    }
+
+    pub(crate) fn holds(self, a: Word, b: Word) -> bool {
+        let (sa, sb) = (a as i64, b as i64);
+        match self {
+            Cmp::Eq => a == b,
+            Cmp::Ne => a != b,
+            Cmp::Lt => a < b,
+            Cmp::Le => a <= b,
+            Cmp::Gt => a > b,
+            Cmp::Ge => a >= b,
+            Cmp::SLt => sa < sb,
+            Cmp::SLe => sa <= sb,
+            Cmp::SGt => sa > sb,
+            Cmp::SGe => sa >= sb
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[repr(u8)]
+pub enum AsmIns {
+    Include(Label),
+    Move(Val, Var),
+    Swap(Var, Var),
+    Add(Var, Val),
+    Sub(Var, Val),
+    Mul(Var, Val),
+    Div(Var, Val),
+    Mod(Var, Val),
+    SDiv(Var, Val),
+    SMod(Var, Val),
+    And(Var, Val),
+    Or(Var, Val),
+    Xor(Var, Val),
+    Shl(Var, Val),
+    Shr(Var, Val),
+    Sar(Var, Val),
+    Rol(Var, Val),
+    Ror(Var, Val),
+    // these only look at the value, the result goes into the variable
+    Neg(Var, Val),
+    Not(Var, Val),
+    Popcnt(Var, Val),
+    Clz(Var, Val),
+    Ctz(Var, Val),
+    Label(Label),
+    JZ(Val, Label),
+    JNz(Val, Label),
+    Jmp(Label),
+    JCmp(Cmp, Val, Val, Label),
+    // jumps if the call right before raised an error
+    JErr(Label),
+    TakeInput,
+    CopyInput,
+    Nop,
+    Output(Val),
+    Call(Label, Vec<Val>, Option<Var>),
+    Function(Label, Vec<Label>, Vec<AsmIns>),
+    Return(Option<Val>)
+}
+
+impl AsmIns {
+    // the values and variables the instruction reads or writes, a FN's body has its own
+    fn operands(&self) -> (Vec<&Val>, Vec<&Var>) {
+        let (mut vals, mut vars) = (Vec::new(), Vec::new());
+        match self {
+            AsmIns::Move(a, b) => { vals.push(a); vars.push(b); }
+            AsmIns::Swap(a, b) => { vars.push(a); vars.push(b); }
+            AsmIns::Add(a, b) | AsmIns::Sub(a, b) | AsmIns::Mul(a, b) | AsmIns::Div(a, b) | AsmIns::Mod(a, b)
+            | AsmIns::SDiv(a, b) | AsmIns::SMod(a, b) | AsmIns::And(a, b) | AsmIns::Or(a, b) | AsmIns::Xor(a, b)
+            | AsmIns::Shl(a, b) | AsmIns::Shr(a, b) | AsmIns::Sar(a, b) | AsmIns::Rol(a, b) | AsmIns::Ror(a, b)
+            | AsmIns::Neg(a, b) | AsmIns::Not(a, b) | AsmIns::Popcnt(a, b) | AsmIns::Clz(a, b) | AsmIns::Ctz(a, b) => {
+                vars.push(a);
+                vals.push(b);
+            }
+            AsmIns::JZ(a, _) | AsmIns::JNz(a, _) | AsmIns::Output(a) | AsmIns::Return(Some(a)) => { vals.push(a); }
+            AsmIns::JCmp(_, a, b, _) => { vals.push(a); vals.push(b); }
+            AsmIns::Call(_, params, ret) => {
+                vals.extend(params);
+                vars.extend(ret);
+            }
+            _ => { }
+        }
+
+        (vals, vars)
+    }
+
+    // every named variable the instruction reads or writes
+    pub fn variables(&self) -> Vec<&Label> {
+        fn val<'a>(v: &'a Val, out: &mut Vec<&'a Label>) {
+            if let Val::Var(v) = v { var(v, out); }
+        }
+
+        fn var<'a>(v: &'a Var, out: &mut Vec<&'a Label>) {
+            match v {
+                Var::Named(lbl) => { out.push(lbl); }
+                Var::Addr(addr, _) => { val(addr, out); }
+            }
+        }
+
+        let mut out = Vec::new();
+        let (vals, vars) = self.operands();
+        vals.into_iter().for_each(|v| val(v, &mut out));
+        vars.into_iter().for_each(|v| var(v, &mut out));
+        out
+    }
+}
+
+// CALLs of this give the error code of the instruction right before it, 0 if that went fine
+pub(crate) const ERRNO: &str = "errno";
+
+// whether `next` catches the error of the instruction right before it, otherwise the error
+// makes every function return until it gets to whoever runs the program
+pub(crate) fn catches(next: Option<&AsmIns>) -> bool {
+    match next {
+        Some(AsmIns::JErr(_)) => true,
+        Some(AsmIns::Call(name, params, _)) => name == ERRNO && params.is_empty(),
+        _ => false
+    }
+}
+
+// what fuel is taken for, labels and what's only there for the compiler don't run
+pub(crate) fn burns(ins: &AsmIns) -> bool {
+    !matches!(ins, AsmIns::Label(_) | AsmIns::Function(..) | AsmIns::Include(_))
+}
+
+// where every instruction of main and then of each FN (in order) is in the whole program, counted
+// the same way as `parser::asm_spanned`'s spans, a FN's header comes right before its body
+pub(crate) fn positions(ins: &[AsmIns]) -> Vec<Vec<usize>> {
+    let mut main = Vec::new();
+    let mut bodies = Vec::new();
+    let mut at = 0;
+
+    for i in ins {
+        main.push(at);
+        at += 1;
+        if let AsmIns::Function(_, _, body) = i {
+            bodies.push((at..at + body.len()).collect());
+            at += body.len();
+        }
+    }
+
+    std::iter::once(main).chain(bodies).collect()
+}
+
+// every string constant in the program, function bodies included, each one once
+pub(crate) fn strings(ins: &[AsmIns]) -> Vec<&str> {
+    fn val<'a>(v: &'a Val, out: &mut Vec<&'a str>) {
+        match v {
+            Val::Const(Const::Str(s)) if !out.iter().any(|o| o.as_ptr() == s.as_ptr()) => { out.push(s); }
+            Val::Var(v) => { var(v, out); }
+            _ => { }
+        }
+    }
+
+    fn var<'a>(v: &'a Var, out: &mut Vec<&'a str>) {
+        if let Var::Addr(addr, _) = v { val(addr, out); }
+    }
+
+    let mut out: Vec<&str> = Vec::new();
+    for i in ins {
+        if let AsmIns::Function(_, _, body) = i {
+            for s in strings(body) {
+                if !out.iter().any(|o| o.as_ptr() == s.as_ptr()) { out.push(s); }
+            }
+        }
+
+        let (vals, vars) = i.operands();
+        vals.into_iter().for_each(|v| val(v, &mut out));
+        vars.into_iter().for_each(|v| var(v, &mut out));
+    }
+
+    out
+}
+
+// what a sandbox's memory starts out as, nothing where 0 points and then every string with its nul,
+// by where each string is in the program, the check pass makes sure it fits
+pub(crate) fn image(ins: &[AsmIns]) -> (Vec<u8>, HashMap<usize, Word>) {
+    let mut image = vec![0; rba_runtime::SANDBOX_NULL as usize];
+    let mut offsets = HashMap::new();
+    for string in strings(ins) {
+        offsets.insert(string.as_ptr() as usize, image.len() as Word);
+        image.extend_from_slice(string.as_bytes());
+        image.push(0);
+    }
+
+    (image, offsets)
+}
+
+pub(crate) fn isa() -> OwnedTargetIsa {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    // FIXME set back to true once the x64 backend supports it.
+    flag_builder.set("is_pic", "true").unwrap();
+    flag_builder.set("opt_level", "speed").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+
+    let flags = settings::Flags::new(flag_builder);
+    isa_builder
+        .finish(flags)
+        .unwrap()
+}
+
+// the cranelift ir the jit would compile the program to, nothing is finalized so no symbols need to resolve
+pub fn into_ir(ins: &[AsmIns]) -> String {
+    let mut module = JITModule::new(JITBuilder::with_isa(isa(), default_libcall_names()));
+    let sig_main = module.make_signature();
+
+    let mut linking = Linking { ir: Some(String::new()), ..Linking::default() };
+    compile(&mut module, ins, sig_main, &mut linking);
+
+    linking.ir.unwrap()
+}
+
+// how a program reaches code that isn't its own
+#[derive(Default)]
+pub(crate) struct Linking {
+    // what to import a CALL (or OUT's printc) as, anything missing is imported by its own name
+    pub symbols: HashMap<String, String>,
+    // called before main returns
+    pub finish: Option<String>,
+    // when set, the cranelift ir of every function is written here as it gets compiled
+    pub ir: Option<String>,
+    // when set, main takes a pointer to a Word for each of these variables, they start out
+    // with what it points to and are written back before main returns
+    pub state: Option<Vec<Label>>,
+    // closures called through `rba_host_call`, by name with the address of their HostFn
+    pub host: Vec<(String, *const HostFn)>,
+    // module functions, they are declared as this instead of how they happen to be called
+    pub signatures: HashMap<String, FnSig>,
+    // checked memory mode, every load and store is checked against what malloc gave out
+    // (malloc and free should be imported as rba_malloc and rba_free)
+    pub checked: bool,
+    // addresses are offsets into memory main gets from rba_sandbox (malloc and free should be
+    // imported as rba_sandbox_malloc and rba_sandbox_free), anything the sandbox doesn't allow traps
+    pub sandbox: Option<Sandbox>,
+    // how many instructions a run can go on for, taken before anything that can loop
+    pub fuel: Option<Word>
+}
+
+// where the program's strings end up
+#[derive(Default)]
+struct Strings {
+    // a data object for each one, or with a sandbox an offset into its memory
+    data: HashMap<usize, DataId>,
+    offsets: HashMap<usize, Word>,
+    // what main lets a checked program use
+    allow: Vec<(DataId, usize)>,
+    // what a sandbox's memory starts out as and how long that is
+    image: Option<(DataId, usize)>
+}
+
+// builds main and every FN into `module`, strings end up as read only data objects
+pub(crate) fn compile<M: Module>(module: &mut M, ins: &[AsmIns], sig_main: Signature, linking: &mut Linking) -> FuncId {
+    let mut ctx = module.make_context();
+    let mut func_ctx = FunctionBuilderContext::new();
+
+    // every function has its own Memory space, parameters are its first variables and `at` is where each
+    // instruction is in the program, `main` sets up what the others use
+    #[allow(clippy::too_many_arguments)]
+    fn make_function<M: Module>(func_s: FuncId, signature: Signature, params: &[Label], user: &HashMap<Label, FuncId>,
+                     linking: &Linking, finish: Option<&str>, state: Option<&[Label]>, strings: &Strings, depth: DataId,
+                     main: bool, module: &mut M, ctx: &mut Context, func_ctx: &mut FunctionBuilderContext,
+                     ins: &[AsmIns], at: &[usize]) {
+        let ret_ty = signature.returns.first().map(|p| p.value_type);
+        let symbol = |name: &str| linking.symbols.get(name).cloned().unwrap_or_else(|| name.to_string());
+
+        let mut sig = module.make_signature();
+        sig.params.push(AbiParam::new(N_TYPE));
+
+        let callee = module
+            .declare_function(&symbol("printc"), Linkage::Import, &sig)
+            .map_err(|e| e.to_string()).unwrap();
+
+        ctx.func.signature = signature;
+        ctx.func.name = UserFuncName::user(0, func_s.as_u32());
+
+        let local_callee = module
+            .declare_func_in_func(callee, &mut ctx.func);
+
+        let finish = finish.map(|name| {
+            let mut sig = module.make_signature();
+            sig.returns.push(AbiParam::new(types::I32));
+            let callee = module.declare_function(name, Linkage::Import, &sig).unwrap();
+            module.declare_func_in_func(callee, &mut ctx.func)
+        });
+
+        let mut import = |name: &str, params: usize, returns: bool| {
+            let mut sig = module.make_signature();
+            sig.params.extend(vec![AbiParam::new(N_TYPE); params]);
+            if returns { sig.returns.push(AbiParam::new(N_TYPE)); }
+            let callee = module.declare_function(name, Linkage::Import, &sig).unwrap();
+            module.declare_func_in_func(callee, &mut ctx.func)
+        };
+
+        let failed = import("rba_failed", 0, true);
+        let take_error = import("rba_take_error", 0, true);
+        let locate = import("rba_locate", 1, false);
+        let trap = import("rba_trap", 3, false);
+        let check = linking.checked.then(|| import("rba_check", 2, true));
+        let allow_fn = import("rba_allow", 2, false);
+        let memory = linking.sandbox.as_ref().map(|_| {
+            if main { import("rba_sandbox", 3, true) } else { import("rba_memory", 0, true) }
+        });
+        let fuel = linking.fuel.map(|_| {
+            if main { import("rba_refuel", 1, true) } else { import("rba_fuel", 0, true) }
+        });
+
+        // host closures are in `user` too, through their trampolines
+        let denied = |label: &Label| linking.sandbox.as_ref().is_some_and(|s| s.denies(label, user.contains_key(label)));
+
+        let mut function_lookup = HashMap::new();
+
+        for i in ins {
+            if let AsmIns::Call(label, params, out) = i {
+                if function_lookup.contains_key(label) || label == ERRNO || denied(label) { continue; }
+
+                if let Some(callee) = user.get(label) {
+                    let func_ref = module.declare_func_in_func(*callee, &mut ctx.func);
+                    function_lookup.insert(label, func_ref);
+                    continue;
+                }
+
+                // C functions have nothing better to go off than the call
+                let known = linking.signatures.get(label).copied().unwrap_or(FnSig::new(params.len(), out.is_some()));
+                assert_eq!(known.params, params.len(), "`{label}` takes {} argument(s) but {} were given", known.params, params.len());
+                assert!(known.returns || out.is_none(), "`{label}` does not return a value");
+
+                let mut sig = module.make_signature();
+                for _ in 0..known.params {
+                    sig.params.push(AbiParam::new(N_TYPE));
+                }
+                if known.returns { sig.returns.push(AbiParam::new(N_TYPE)); }
+
+                let callee = module
+                    .declare_function(&symbol(label), Linkage::Import, &sig)
+                    .map_err(|e| e.to_string()).unwrap();
+
+                let func_ref = module.declare_func_in_func(callee, &mut ctx.func);
+
+                function_lookup.insert(label, func_ref);
+            }
+        }
+
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+
+        let vidx = 0;
+        let variable_lookup = HashMap::new();
+        let mut block_lookup = HashMap::new();
+
+        struct Env<'m, M> {
+            vl: HashMap<Label, Variable>,
+            vi: usize,
+            module: &'m mut M,
+            strings: &'m Strings,
+            gvs: HashMap<DataId, GlobalValue>,
+            // rba_check, when memory is checked
+            check: Option<FuncRef>,
+            // where a sandbox's memory is and how big it is
+            memory: Option<(Value, Word)>,
+            // where what's left of the fuel is
+            fuel: Option<Value>,
+            trap: FuncRef,
+            // the code of the last error JERR or errno caught
+            error: Variable,
+            // returns with the error for whoever called the function
+            bail: Block,
+            // the instruction being compiled, and where a trap in it goes when the next one catches it
+            at: Word,
+            caught: Option<Block>
+        }
+
+        let error = Variable::new(vidx);
+        bcx.declare_var(error, N_TYPE);
+
+        let mut env = Env {
+            vl: variable_lookup,
+            vi: vidx + 1,
+            module,
+            strings,
+            gvs: HashMap::new(),
+            check,
+            memory: None,
+            fuel: None,
+            trap,
+            error,
+            bail: bcx.create_block(),
+            at: 0,
+            caught: None
+        };
+
+        // a trap nothing catches raises an error and bails, the code after it is never reached
+        fn trap1<M: Module>(code: Word, addr: Value, bcx: &mut FunctionBuilder, env: &mut Env<M>) {
+            match env.caught {
+                Some(done) => {
+                    let code = bcx.ins().iconst(N_TYPE, code as i64);
+                    bcx.def_var(env.error, code);
+                    bcx.ins().jump(done, &[]);
+                }
+                None => {
+                    let (code, at) = (bcx.ins().iconst(N_TYPE, code as i64), bcx.ins().iconst(N_TYPE, env.at as i64));
+                    bcx.ins().call(env.trap, &[code, at, addr]);
+                    bcx.ins().jump(env.bail, &[]);
+                }
+            }
+        }
+
+        // traps unless `cond` is nonzero
+        fn guard1<M: Module>(cond: Value, code: Word, addr: Value, bcx: &mut FunctionBuilder, env: &mut Env<M>) {
+            let (fail, ok) = (bcx.create_block(), bcx.create_block());
+            bcx.ins().brif(cond, ok, &[], fail, &[]);
+            bcx.switch_to_block(fail);
+            trap1(code, addr, bcx, env);
+            bcx.switch_to_block(ok);
+        }
+
+        // checked memory mode, the access has to be inside something malloc gave out
+        fn check_addr1<M: Module>(addr: Value, width: Width, bcx: &mut FunctionBuilder, env: &mut Env<M>) {
+            if let Some(check) = env.check {
+                let len = bcx.ins().iconst(N_TYPE, width.bytes() as i64);
+                let inst = bcx.ins().call(check, &[addr, len]);
+                let ok = bcx.inst_results(inst)[0];
+                guard1(ok, Error::BAD_ADDRESS, addr, bcx, env);
+            }
+        }
+
+        // where `len` bytes at `addr` really are, in a sandbox it's an offset that has to be inside its memory
+        fn bounds1<M: Module>(addr: Value, len: Word, bcx: &mut FunctionBuilder, env: &mut Env<M>) -> Value {
+            match env.memory {
+                Some((base, size)) => {
+                    let inside = match size.checked_sub(len) {
+                        Some(last) => bcx.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, addr, last as i64),
+                        None => bcx.ins().iconst(types::I8, 0)
+                    };
+                    guard1(inside, Error::OUT_OF_BOUNDS, addr, bcx, env);
+                    bcx.ins().iadd(base, addr)
+                }
+                None => addr
+            }
+        }
+
+        // bounds1 for a length only known when it runs, a Len argument
+        fn range1<M: Module>(addr: Value, len: Value, bcx: &mut FunctionBuilder, env: &mut Env<M>) -> Value {
+            match env.memory {
+                Some((base, size)) => {
+                    // len <= size && addr <= size - len, so nothing overflows
+                    let fits = bcx.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, len, size as i64);
+                    let size = bcx.ins().iconst(N_TYPE, size as i64);
+                    let last = bcx.ins().isub(size, len);
+                    let starts = bcx.ins().icmp(IntCC::UnsignedLessThanOrEqual, addr, last);
+                    let inside = bcx.ins().band(fits, starts);
+                    guard1(inside, Error::OUT_OF_BOUNDS, addr, bcx, env);
+                    bcx.ins().iadd(base, addr)
+                }
+                None => addr
+            }
+        }
+
+        // takes the fuel for the `ran` instructions since it was last taken, the program stops without enough
+        // (the last one of them hasn't run yet when it's `jumping`)
+        fn burn1<M: Module>(ran: Word, jumping: bool, bcx: &mut FunctionBuilder, env: &mut Env<M>) {
+            let Some(fuel) = env.fuel.filter(|_| ran > 0) else { return };
+
+            let left = bcx.ins().load(N_TYPE, MemFlags::new(), fuel, 0);
+            let enough = bcx.ins().icmp_imm(IntCC::UnsignedGreaterThanOrEqual, left, ran as i64);
+            let (empty, ok) = (bcx.create_block(), bcx.create_block());
+            bcx.ins().brif(enough, ok, &[], empty, &[]);
+
+            bcx.switch_to_block(empty);
+            let code = bcx.ins().iconst(N_TYPE, Error::OUT_OF_FUEL as i64);
+            let done = ran - jumping as Word;
+            let (at, done) = (bcx.ins().iconst(N_TYPE, env.at as i64), bcx.ins().iconst(N_TYPE, done as i64));
+            bcx.ins().call(env.trap, &[code, at, done]);
+            bcx.ins().jump(env.bail, &[]);
+
+            bcx.switch_to_block(ok);
+            let left = bcx.ins().iadd_imm(left, -(ran as i64));
+            bcx.ins().store(MemFlags::new(), left, fuel, 0);
+        }
+
+        fn get_val1<M: Module>(v: Val, bcx: &mut FunctionBuilder, env: &mut Env<M>) -> Value {
+            match v {
+                Val::Var(v) => { get_var1(v, bcx, env) }
+                Val::Const(Const::Word(word)) => {
+                    bcx.ins().iconst(N_TYPE, word as i64)
+                }
+                Val::Const(Const::Str(string)) if env.memory.is_some() => {
+                    bcx.ins().iconst(N_TYPE, env.strings.offsets[&(string.as_ptr() as usize)] as i64)
+                }
+                Val::Const(Const::Str(string)) => {
+                    let id = env.strings.data[&(string.as_ptr() as usize)];
+                    let gv = *env.gvs.entry(id).or_insert_with(|| env.module.declare_data_in_func(id, bcx.func));
+                    bcx.ins().symbol_value(N_TYPE, gv)
+                }
+            }
+        }
+
+        fn get_var1<M: Module>(v: Var, bcx: &mut FunctionBuilder, env: &mut Env<M>) -> Value {
+            match v {
+                Var::Named(label) => {
+                    if let Some(v) = env.vl.get(&label) {
+                        bcx.use_var(*v)
+                    } else {
+                        let v = Variable::new(env.vi);
+                        env.vi += 1;
+
+                        env.vl.insert(label, v);
+                        bcx.declare_var(v, N_TYPE);
+                        bcx.use_var(v)
+                    }
+                }
+                Var::Addr(bval, width) => {
+                    let addr = get_val1(*bval, bcx, env);
+                    check_addr1(addr, width, bcx, env);
+                    let addr = bounds1(addr, width.bytes(), bcx, env);
+                    match width {
+                        Width::W8 => bcx.ins().uload8(N_TYPE, MemFlags::new(), addr, 0),
+                        Width::W16 => bcx.ins().uload16(N_TYPE, MemFlags::new(), addr, 0),
+                        Width::W32 => bcx.ins().uload32(MemFlags::new(), addr, 0),
+                        Width::W64 => bcx.ins().load(N_TYPE, MemFlags::new(), addr, 0)
+                    }
+                }
+            }
+        }
+
+        fn set_var1<M: Module>(var: Var, to: Value,  bcx: &mut FunctionBuilder, env: &mut Env<M>) {
+            match var {
+                Var::Named(label) => {
+                    if let Some(v) = env.vl.get(&label) {
+                        bcx.def_var(*v, to)
+                    } else {
+                        let v = Variable::new(env.vi);
+                        env.vi += 1;
+
+                        env.vl.insert(label, v);
+                        bcx.declare_var(v, N_TYPE);
+                        bcx.def_var(v, to)
+                    }
+                }
+                Var::Addr(bval, width) => {
+                    let addr = get_val1(*bval, bcx, env);
+                    check_addr1(addr, width, bcx, env);
+                    let addr = bounds1(addr, width.bytes(), bcx, env);
+                    match width {
+                        Width::W8 => bcx.ins().istore8(MemFlags::new(), to, addr, 0),
+                        Width::W16 => bcx.ins().istore16(MemFlags::new(), to, addr, 0),
+                        Width::W32 => bcx.ins().istore32(MemFlags::new(), to, addr, 0),
+                        Width::W64 => bcx.ins().store(MemFlags::new(), to, addr, 0)
+                    };
+                }
+            }
+        }
+
+        // dividing by 0 traps, constants other than 0 don't need checking
+        let divisor = |val: &Val, v: Value, bcx: &mut FunctionBuilder, env: &mut Env<M>| {
+            if !matches!(val, Val::Const(Const::Word(w)) if *w != 0) {
+                guard1(v, Error::DIV_ZERO, v, bcx, env);
+            }
+        };
+        let get_value = |r: &Val, bcx: &mut FunctionBuilder, env: &mut Env<M>| { get_val1(r.clone(), bcx, env) };
+        let get_var = |v: &Var, bcx: &mut FunctionBuilder, env: &mut Env<M>| { get_var1(v.clone(), bcx, env) };
+        let set_var = |v: &Var, val: Value, bcx: &mut FunctionBuilder, env: &mut Env<M>| { set_var1(v.clone(), val, bcx, env) };
+
+        // main's value (if it has one) is an exit status from finish, not something RET gives
+        fn return_(val: Option<Value>, ret_ty: Option<Type>, finish: Option<FuncRef>, save: &Option<(Value, Vec<Variable>)>,
+                   bcx: &mut FunctionBuilder) {
+            if let Some((ptr, vars)) = save {
+                for (idx, v) in vars.iter().enumerate() {
+                    let val = bcx.use_var(*v);
+                    bcx.ins().store(MemFlags::new(), val, *ptr, (idx * PTR_LEN) as i32);
+                }
+            }
+            let val = val.or_else(|| finish.map(|finish| {
+                let inst = bcx.ins().call(finish, &[]);
+                bcx.inst_results(inst)[0]
+            }));
+
+            match ret_ty {
+                Some(ty) => {
+                    let val = val.unwrap_or_else(|| bcx.ins().iconst(ty, 0));
+                    bcx.ins().return_(&[val]);
+                }
+                None => { bcx.ins().return_(&[]); }
+            }
+        }
+
+        bcx.append_block_params_for_function_params(block);
+        for (idx, param) in params.iter().enumerate() {
+            let val = bcx.block_params(block)[idx];
+            set_var(&Var::Named(param.clone()), val, &mut bcx, &mut env);
+        }
+
+        let save = state.map(|vars| {
+            let ptr = bcx.block_params(block)[0];
+            let vars = vars.iter().enumerate().map(|(idx, name)| {
+                let val = bcx.ins().load(N_TYPE, MemFlags::new(), ptr, (idx * PTR_LEN) as i32);
+                set_var(&Var::Named(name.clone()), val, &mut bcx, &mut env);
+                env.vl[name]
+            }).collect();
+            (ptr, vars)
+        });
+
+        for i in ins {
+            if let AsmIns::Label(id) = i {
+                let bl = bcx.create_block();
+                block_lookup.insert(id, bl);
+            }
+        }
+
+        let zero = bcx.ins().iconst(N_TYPE, 0);
+        bcx.def_var(error, zero);
+
+        // a sandbox's memory is the run's, main makes it and the others find it
+        env.memory = linking.sandbox.as_ref().zip(memory).map(|(sandbox, memory)| {
+            let args = match strings.image {
+                Some((id, len)) if main => {
+                    let gv = env.module.declare_data_in_func(id, bcx.func);
+                    let image = bcx.ins().symbol_value(N_TYPE, gv);
+                    vec![bcx.ins().iconst(N_TYPE, sandbox.memory as i64), image, bcx.ins().iconst(N_TYPE, len as i64)]
+                }
+                _ => Vec::new()
+            };
+            let inst = bcx.ins().call(memory, &args);
+            (bcx.inst_results(inst)[0], sandbox.memory as Word)
+        });
+
+        // the fuel is the run's too, main fills it up
+        env.fuel = linking.fuel.zip(fuel).map(|(budget, fuel)| {
+            let args = if main { vec![bcx.ins().iconst(N_TYPE, budget as i64)] } else { Vec::new() };
+            let inst = bcx.ins().call(fuel, &args);
+            bcx.inst_results(inst)[0]
+        });
+
+        // how many FN calls are going, main starts it over for the run
+        let gv = env.module.declare_data_in_func(depth, bcx.func);
+        let depth = bcx.ins().symbol_value(N_TYPE, gv);
+        if main {
+            bcx.ins().store(MemFlags::new(), zero, depth, 0);
+        }
+
+        // a checked program can use its strings like anything it malloced (a sandbox has them already)
+        if linking.checked && main && env.memory.is_none() {
+            for (id, len) in &strings.allow {
+                let gv = env.module.declare_data_in_func(*id, bcx.func);
+                let ptr = bcx.ins().symbol_value(N_TYPE, gv);
+                let len = bcx.ins().iconst(N_TYPE, *len as i64);
+                bcx.ins().call(allow_fn, &[ptr, len]);
+            }
+        }
+
+        // after a call that can raise an error, either the next instruction catches it or the function bails
+        // (running out of fuel can't be caught, it's still there after taking the error)
+        let raised = |next: Option<&AsmIns>, bcx: &mut FunctionBuilder, env: &Env<M>| {
+            if catches(next) {
+                let inst = bcx.ins().call(take_error, &[]);
+                let code = bcx.inst_results(inst)[0];
+                bcx.def_var(error, code);
+                if env.fuel.is_none() {
+                    return;
+                }
+            }
+
+            let inst = bcx.ins().call(failed, &[]);
+            let failed = bcx.inst_results(inst)[0];
+
+            let (fail, eb) = (bcx.create_block(), bcx.create_block());
+            bcx.ins().brif(failed, fail, &[], eb, &[]);
+            bcx.switch_to_block(fail);
+            let at = bcx.ins().iconst(N_TYPE, env.at as i64);
+            bcx.ins().call(locate, &[at]);
+            bcx.ins().jump(env.bail, &[]);
+            bcx.switch_to_block(eb);
+        };
+
+        // instructions since fuel was last taken
+        let mut ran = 0;
+
+        for (idx, i) in ins.iter().enumerate() {
+            let next = ins.get(idx + 1);
+
+            // fuel is taken before anything that leaves the instructions running one after the other, and
+            // before any call, OUT or input, so without enough of it nothing outside the program sees a thing
+            env.at = at[idx] as Word;
+            if let AsmIns::Label(_) = i {
+                burn1(ran, false, &mut bcx, &mut env);
+                ran = 0;
+            }
+            ran += burns(i) as Word;
+            if matches!(i, AsmIns::JZ(..) | AsmIns::JNz(..) | AsmIns::Jmp(_) | AsmIns::JCmp(..) | AsmIns::JErr(_) | AsmIns::Return(_)
+                | AsmIns::Call(..) | AsmIns::Output(_) | AsmIns::TakeInput | AsmIns::CopyInput) {
+                burn1(ran, true, &mut bcx, &mut env);
+                ran = 0;
+            }
+
+            // the error JERR and errno see is this instruction's, 0 unless it traps or a call raises one
+            if catches(next) && !catches(Some(i)) {
+                env.caught = Some(bcx.create_block());
+                let zero = bcx.ins().iconst(N_TYPE, 0);
+                bcx.def_var(error, zero);
+            }
+
+            match i {
+                AsmIns::Move(val, var) => {
+                    let val = get_value(val, &mut bcx, &mut env);
+                    set_var(var, val, &mut bcx, &mut env);
+                }
+                AsmIns::Swap(vr1, vr2) => {
+                    let r1 = get_var(vr1, &mut bcx, &mut env);
+                    let r2 = get_var(vr2, &mut bcx, &mut env);
+
+                    set_var(vr2, r1, &mut bcx, &mut env);
+                    set_var(vr1, r2, &mut bcx, &mut env);
+                }
+                AsmIns::Add(var, val) => {
+                    let v1 = get_var(var, &mut bcx, &mut env);
+                    let v2 = get_value(val, &mut bcx, &mut env);
+
+                    let v3 = bcx.ins().iadd(v1, v2);
+                    set_var(var, v3, &mut bcx, &mut env);
+                }
+                AsmIns::Sub(var, val) => {
+                    let v1 = get_var(var, &mut bcx, &mut env);
+                    let v2 = get_value(val, &mut bcx, &mut env);
+
+                    let v3 = bcx.ins().isub(v1, v2);
+                    set_var(var, v3, &mut bcx, &mut env);
+                }
+                AsmIns::Mul(var, val) => {
+                    let v1 = get_var(var, &mut bcx, &mut env);
+                    let v2 = get_value(val, &mut bcx, &mut env);
+
+                    let v3 = bcx.ins().imul(v1, v2);
+                    set_var(var, v3, &mut bcx, &mut env);
+                }
+                AsmIns::Div(var, val) => {
+                    let v1 = get_var(var, &mut bcx, &mut env);
+                    let v2 = get_value(val, &mut bcx, &mut env);
+                    divisor(val, v2, &mut bcx, &mut env);
+
+                    let v3 = bcx.ins().udiv(v1, v2);
+                    set_var(var, v3, &mut bcx, &mut env);
+                }
+                AsmIns::Mod(var, val) => {
+                    let v1 = get_var(var, &mut bcx, &mut env);
+                    let v2 = get_value(val, &mut bcx, &mut env);
+                    divisor(val, v2, &mut bcx, &mut env);
+
+                    let v3 = bcx.ins().urem(v1, v2);
+                    set_var(var, v3, &mut bcx, &mut env);
+                }
+                AsmIns::SDiv(var, val) | AsmIns::SMod(var, val) => {
+                    let v1 = get_var(var, &mut bcx, &mut env);
+                    let v2 = get_value(val, &mut bcx, &mut env);
+                    divisor(val, v2, &mut bcx, &mut env);
+
+                    // MIN / -1 traps on x86, divide by 1 instead and fix the result up so it wraps like the interpreter
+                    let minus_one = bcx.ins().icmp_imm(IntCC::Equal, v2, -1);
+                    let one = bcx.ins().iconst(N_TYPE, 1);
+                    let divisor = bcx.ins().select(minus_one, one, v2);
+
+                    let v3 = if let AsmIns::SDiv(..) = i {
+                        let quot = bcx.ins().sdiv(v1, divisor);
+                        let neg = bcx.ins().ineg(v1);
+                        bcx.ins().select(minus_one, neg, quot)
+                    } else {
+                        bcx.ins().srem(v1, divisor)
+                    };
+                    set_var(var, v3, &mut bcx, &mut env);
+                }
+                AsmIns::And(var, val) | AsmIns::Or(var, val) | AsmIns::Xor(var, val)
+                | AsmIns::Shl(var, val) | AsmIns::Shr(var, val) | AsmIns::Sar(var, val)
+                | AsmIns::Rol(var, val) | AsmIns::Ror(var, val) => {
+                    let v1 = get_var(var, &mut bcx, &mut env);
+                    let v2 = get_value(val, &mut bcx, &mut env);
+
+                    // shift and rotate amounts wrap at 64, same as the interpreter
+                    let v3 = match i {
+                        AsmIns::And(..) => bcx.ins().band(v1, v2),
+                        AsmIns::Or(..) => bcx.ins().bor(v1, v2),
+                        AsmIns::Xor(..) => bcx.ins().bxor(v1, v2),
+                        AsmIns::Shl(..) => bcx.ins().ishl(v1, v2),
+                        AsmIns::Shr(..) => bcx.ins().ushr(v1, v2),
+                        AsmIns::Sar(..) => bcx.ins().sshr(v1, v2),
+                        AsmIns::Rol(..) => bcx.ins().rotl(v1, v2),
+                        _ => bcx.ins().rotr(v1, v2)
+                    };
+                    set_var(var, v3, &mut bcx, &mut env);
+                }
+                AsmIns::Neg(var, val) | AsmIns::Not(var, val) | AsmIns::Popcnt(var, val) | AsmIns::Clz(var, val) | AsmIns::Ctz(var, val) => {
+                    let v1 = get_value(val, &mut bcx, &mut env);
+
+                    let v2 = match i {
+                        AsmIns::Neg(..) => bcx.ins().ineg(v1),
+                        AsmIns::Not(..) => bcx.ins().bnot(v1),
+                        AsmIns::Popcnt(..) => bcx.ins().popcnt(v1),
+                        AsmIns::Clz(..) => bcx.ins().clz(v1),
+                        _ => bcx.ins().ctz(v1)
+                    };
+                    set_var(var, v2, &mut bcx, &mut env);
+                }
+                AsmIns::JZ(val, addr) => {
+                    let bl = *block_lookup.get(addr).unwrap();
+                    let eb = bcx.create_block();
+
+                    let bool = get_value(val, &mut bcx, &mut env);
+                    bcx.ins().brif(bool, eb, &[], bl, &[]);
+                    bcx.switch_to_block(eb);
+                    // bcx.seal_block(eb);
+                }
+                AsmIns::JNz(val, addr) => {
+                    let bl = *block_lookup.get(&addr).unwrap();
+                    let eb = bcx.create_block();
+
+                    let bool = get_value(val, &mut bcx, &mut env);
+                    bcx.ins().brif(bool, bl, &[], eb, &[]);
+                    bcx.switch_to_block(eb);
+                    // bcx.seal_block(eb);
+                }
+                AsmIns::Jmp(addr) => {
+                    let bl = *block_lookup.get(addr).unwrap();
+                    bcx.ins().jump(bl, &[]);
+
+                    // anything after the jump still needs a block to live in
+                    let eb = bcx.create_block();
+                    bcx.switch_to_block(eb);
+                }
+                AsmIns::JCmp(cmp, a, b, addr) => {
+                    let bl = *block_lookup.get(addr).unwrap();
+                    let eb = bcx.create_block();
+
+                    let a = get_value(a, &mut bcx, &mut env);
+                    let b = get_value(b, &mut bcx, &mut env);
+                    let bool = bcx.ins().icmp(cmp.int_cc(), a, b);
+                    bcx.ins().brif(bool, bl, &[], eb, &[]);
+                    bcx.switch_to_block(eb);
+                }
+                AsmIns::Label(id) => {
+                    let bl = *block_lookup.get(&id).unwrap();
+                    bcx.ins().jump(bl, &[]);
+
+                    bcx.insert_block_after(bl, bcx.current_block().unwrap());
+                    bcx.switch_to_block(bl);
+                }
+                AsmIns::JErr(addr) => {
+                    let bl = *block_lookup.get(addr).unwrap();
+                    let eb = bcx.create_block();
+
+                    let code = bcx.use_var(error);
+                    bcx.ins().brif(code, bl, &[], eb, &[]);
+                    bcx.switch_to_block(eb);
+                }
+                AsmIns::Output(val) => {
+                    let val= get_value(val, &mut bcx, &mut env);
+                    bcx.ins().call(local_callee, &[val]);
+                    raised(next, &mut bcx, &env);
+                }
+                AsmIns::Call(label, _, Some(ret)) if label == ERRNO => {
+                    let code = bcx.use_var(error);
+                    set_var(ret, code, &mut bcx, &mut env);
+                }
+                AsmIns::Call(label, _, None) if label == ERRNO => { }
+                AsmIns::Call(label, _, _) if denied(label) => {
+                    let zero = bcx.ins().iconst(N_TYPE, 0);
+                    trap1(Error::DENIED, zero, &mut bcx, &mut env);
+
+                    let eb = bcx.create_block();
+                    bcx.switch_to_block(eb);
+                }
+                AsmIns::Call(label, params, ret) => {
+                    // module functions get a sandbox's addresses as pointers, after checking that what
+                    // they're going to touch is inside its memory, the Len after an Addr or else a byte
+                    let sig = linking.signatures.get(label).filter(|_| !user.contains_key(label));
+                    let mut args: Vec<Value> = params.iter().map(|arg| get_value(arg, &mut bcx, &mut env)).collect();
+                    for idx in 0..args.len() {
+                        match sig {
+                            Some(sig) if sig.kind(idx) == Kind::Addr && sig.kind(idx + 1) == Kind::Len => {
+                                args[idx] = range1(args[idx], args[idx + 1], &mut bcx, &mut env);
+                            }
+                            Some(sig) if sig.kind(idx) == Kind::Addr => { args[idx] = bounds1(args[idx], 1, &mut bcx, &mut env); }
+                            _ => { }
+                        }
+                    }
+
+                    // a FN counts as one more call going until it returns, past MAX_DEPTH it traps
+                    // instead of running out of stack
+                    let counted = user.contains_key(label) && !linking.host.iter().any(|(name, _)| name == label);
+                    let going = counted.then(|| {
+                        let going = bcx.ins().load(N_TYPE, MemFlags::new(), depth, 0);
+                        let room = bcx.ins().icmp_imm(IntCC::UnsignedLessThan, going, MAX_DEPTH as i64);
+                        guard1(room, Error::TOO_DEEP, going, &mut bcx, &mut env);
+                        let more = bcx.ins().iadd_imm(going, 1);
+                        bcx.ins().store(MemFlags::new(), more, depth, 0);
+                        going
+                    });
+
+                    let inst = bcx.ins().call(*function_lookup.get(label).unwrap(), &args);
+
+                    if let Some(going) = going {
+                        bcx.ins().store(MemFlags::new(), going, depth, 0);
+                    }
+
+                    if let Some(ret) = ret {
+                        let out = bcx.inst_results(inst)[0];
+
+                        set_var(ret, out, &mut bcx, &mut env);
+                    }
+
+                    // C functions don't know about rba's errors
+                    if user.contains_key(label) || linking.signatures.contains_key(label) || label == "printc" {
+                        raised(next, &mut bcx, &env);
+                    }
+                }
+                AsmIns::Return(val) => {
+                    let val = match val {
+                        Some(val) if ret_ty == Some(N_TYPE) => Some(get_value(val, &mut bcx, &mut env)),
+                        _ => None
+                    };
+                    return_(val, ret_ty, finish, &save, &mut bcx);
+
+                    // anything after the return still needs a block to live in
+                    let eb = bcx.create_block();
+                    bcx.switch_to_block(eb);
+                }
+                _ => { }
+            }
+
+            if let Some(done) = env.caught.take() {
+                bcx.ins().jump(done, &[]);
+                bcx.switch_to_block(done);
+            }
+        }
+
+        burn1(ran, false, &mut bcx, &mut env);
+        return_(None, ret_ty, finish, &save, &mut bcx);
+        bcx.switch_to_block(env.bail);
+        return_(None, ret_ty, finish, &save, &mut bcx);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+
+    // declared up front so functions can call each other (and themselves) in any order
+    let mut user = HashMap::new();
+    for i in ins {
+        if let AsmIns::Function(name, params, _) = i {
+            let mut sig = module.make_signature();
+            for _ in params {
+                sig.params.push(AbiParam::new(N_TYPE));
+            }
+            sig.returns.push(AbiParam::new(N_TYPE));
+
+            // local, so a FN can share its name with something in libc
+            let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+            user.insert(name.clone(), (id, sig));
+        }
+    }
+
+    // every host closure gets a function of its own, which puts its arguments on the stack for rba_host_call
+    let mut trampolines = Vec::new();
+    for (name, f) in &linking.host {
+        let params = unsafe { (**f).sig.params };
+
+        let mut sig = module.make_signature();
+        for _ in 0..params {
+            sig.params.push(AbiParam::new(N_TYPE));
+        }
+        sig.returns.push(AbiParam::new(N_TYPE));
+
+        let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+        user.insert(name.clone(), (id, sig.clone()));
+        trampolines.push((id, sig, *f, params));
+    }
+
+    let user_ids = user.iter().map(|(name, (id, _))| (name.clone(), *id)).collect();
+
+    // every string gets a nul after it for C functions
+    let mut placed = Strings::default();
+    match &linking.sandbox {
+        Some(_) => {
+            let (image, offsets) = image(ins);
+            let id = module.declare_anonymous_data(false, false).unwrap();
+            let mut data = DataContext::new();
+            placed.image = Some((id, image.len()));
+            data.define(image.into_boxed_slice());
+            module.define_data(id, &data).unwrap();
+            placed.offsets = offsets;
+        }
+        None => {
+            for string in strings(ins) {
+                let id = module.declare_anonymous_data(false, false).unwrap();
+                let mut data = DataContext::new();
+                data.define([string.as_bytes(), &[0]].concat().into_boxed_slice());
+                module.define_data(id, &data).unwrap();
+
+                placed.data.insert(string.as_ptr() as usize, id);
+                placed.allow.push((id, string.len() + 1));
+            }
+        }
+    }
+
+    // how many FN calls are going, each call site counts itself in and out
+    let depth = module.declare_anonymous_data(true, false).unwrap();
+    let mut data = DataContext::new();
+    data.define_zeroinit(PTR_LEN);
+    module.define_data(depth, &data).unwrap();
+
+    let mut positions = positions(ins).into_iter();
+
+    let func_main = module.declare_function("main", Linkage::Export, &sig_main).unwrap();
+    make_function(func_main, sig_main, &[], &user_ids, linking, linking.finish.as_deref(), linking.state.as_deref(), &placed, depth,
+                  true, module, &mut ctx, &mut func_ctx, ins, &positions.next().unwrap());
+
+    if let Some(ir) = &mut linking.ir {
+        ir.push_str(&format!("; main\n{}\n", ctx.func.display()));
+    }
+    module.define_function(func_main, &mut ctx).unwrap();
+    module.clear_context(&mut ctx);
+
+    for i in ins {
+        if let AsmIns::Function(name, params, body) = i {
+            let (id, sig) = user[name].clone();
+            make_function(id, sig, params, &user_ids, linking, None, None, &placed, depth,
+                          false, module, &mut ctx, &mut func_ctx, body, &positions.next().unwrap());
+
+            if let Some(ir) = &mut linking.ir {
+                ir.push_str(&format!("; {name}\n{}\n", ctx.func.display()));
+            }
+            module.define_function(id, &mut ctx).unwrap();
+            module.clear_context(&mut ctx);
+        }
+    }
+
+    for (id, sig, f, params) in trampolines {
+        let mut host_sig = module.make_signature();
+        host_sig.params.extend([AbiParam::new(N_TYPE); 3]);
+        host_sig.returns.push(AbiParam::new(N_TYPE));
+        let host_call = module.declare_function("rba_host_call", Linkage::Import, &host_sig).unwrap();
+
+        ctx.func.signature = sig;
+        ctx.func.name = UserFuncName::user(0, id.as_u32());
+        let host_call = module.declare_func_in_func(host_call, &mut ctx.func);
+
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+
+        let slot = bcx.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, (params.max(1) * PTR_LEN) as u32));
+        for idx in 0..params {
+            let arg = bcx.block_params(block)[idx];
+            bcx.ins().stack_store(arg, slot, (idx * PTR_LEN) as i32);
+        }
+
+        let f = bcx.ins().iconst(N_TYPE, f as i64);
+        let args = bcx.ins().stack_addr(N_TYPE, slot, 0);
+        let len = bcx.ins().iconst(N_TYPE, params as i64);
+        let inst = bcx.ins().call(host_call, &[f, args, len]);
+        let ret = bcx.inst_results(inst)[0];
+        bcx.ins().return_(&[ret]);
+
+        bcx.seal_all_blocks();
+        bcx.finalize();
+
+        module.define_function(id, &mut ctx).unwrap();
+        module.clear_context(&mut ctx);
+    }
+
+    func_main
+}
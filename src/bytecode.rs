@@ -0,0 +1,778 @@
+use std::collections::HashMap;
+use crate::asm::{self, AsmIns, Cmp, Const, Val, Var, Width, Word, ERRNO};
+use crate::dynamic;
+use crate::engine::Redirect;
+use crate::modules::{FnSig, HostFn, Kind, ModuleProvider};
+use crate::program::{Options, Sandbox};
+use rba_runtime::{Error, Streams, MAX_DEPTH};
+
+// module and C functions can't take more than this, the check pass makes sure of it
+pub(crate) const MAX_NATIVE_ARGS: usize = 16;
+
+type Reg = u32;
+
+// variables are registers in the frame of the function they're used in, constants are inline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arg {
+    Reg(Reg),
+    Imm(Word)
+}
+
+// one operation of the interpreter, jumps go to an index into the function's code
+#[derive(Clone, Debug)]
+pub enum Op {
+    Mov(Reg, Arg),
+    Swap(Reg, Reg),
+    Add(Reg, Arg),
+    Sub(Reg, Arg),
+    Mul(Reg, Arg),
+    Div(Reg, Arg),
+    Mod(Reg, Arg),
+    SDiv(Reg, Arg),
+    SMod(Reg, Arg),
+    And(Reg, Arg),
+    Or(Reg, Arg),
+    Xor(Reg, Arg),
+    Shl(Reg, Arg),
+    Shr(Reg, Arg),
+    Sar(Reg, Arg),
+    Rol(Reg, Arg),
+    Ror(Reg, Arg),
+    Neg(Reg, Arg),
+    Not(Reg, Arg),
+    Popcnt(Reg, Arg),
+    Clz(Reg, Arg),
+    Ctz(Reg, Arg),
+    // register <- width bytes at the address
+    Load(Width, Reg, Arg),
+    // width bytes at the address <- value
+    Store(Width, Arg, Arg),
+    // register <- where an offset into a sandbox's memory really is
+    Addr(Reg, Arg),
+    Jmp(usize),
+    Jz(Arg, usize),
+    Jnz(Arg, usize),
+    JCmp(Cmp, Arg, Arg, usize),
+    Out(Arg),
+    // a user FN, by index into Bytecode::functions
+    Call(usize, Box<[Arg]>, Option<Reg>),
+    // a host closure, by index into Bytecode::host
+    Host(usize, Box<[Arg]>, Option<Reg>),
+    // a module or C function and whether it returns anything
+    Native(*const u8, bool, Box<[Arg]>, Option<Reg>),
+    // the error the call before raised goes into the register, 0 if there wasn't one
+    Catch(Reg),
+    // returns if the call before (at an instruction in the program) raised an error, the caller does the same
+    Check(usize),
+    // traps if the value isn't a divisor or an address that can be used, see `Trap`
+    Guard(Guard, Arg, Trap),
+    // takes fuel for the instruction (at an instruction in the program) about to run, the program stops without any
+    Fuel(usize),
+    Ret(Arg)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Guard {
+    Divisor,
+    // checked memory mode only
+    Memory(Width),
+    // sandboxed only, the offset has to leave this many bytes before the end of the memory
+    Bounds(Word),
+    // the same for a length only known when it runs, a Len argument
+    Range(Arg),
+    // sandboxed only, a function the sandbox doesn't allow is called, always traps
+    Denied,
+    // a FN is called with MAX_DEPTH calls already going
+    Depth
+}
+
+// where a trap happened in the program, and where to go when the instruction after catches it
+// (the rest of the trapping instruction is skipped), nothing catching it makes the function return
+#[derive(Clone, Copy, Debug)]
+pub struct Trap {
+    pub at: usize,
+    pub caught: Option<usize>
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub code: Vec<Op>,
+    // parameters come first
+    pub params: usize,
+    pub regs: usize,
+    // the code of the last error JERR or errno caught
+    pub error: Reg
+}
+
+// a program compiled for the interpreter, main is the first function
+pub struct Bytecode {
+    pub functions: Vec<Function>,
+    host: Vec<(String, HostFn)>,
+    // main's variables and where they live
+    vars: Vec<(String, Reg)>,
+    // every string with its nul, where the program points outside a sandbox,
+    // a checked program can use them besides what it mallocs
+    strings: Vec<Box<[u8]>>,
+    checked: bool,
+    // how big a sandbox's memory is and what it starts out as
+    memory: Option<(Word, Vec<u8>)>,
+    fuel: Option<Word>,
+    streams: Option<Streams>
+}
+
+impl Bytecode {
+    pub(crate) fn compile(ins: &[AsmIns], provider: impl ModuleProvider, options: &Options) -> Bytecode {
+        let mut natives = HashMap::new();
+        natives.insert(String::from("printc"), rba_runtime::rba_printc as *const u8);
+        natives.insert(String::from("malloc"), libc::malloc as *const u8);
+        natives.insert(String::from("atol"), libc::atol as *const u8);
+        for (name, _, ptr) in options.allocator() {
+            natives.insert(name.to_string(), ptr);
+        }
+
+        let mut sigs = HashMap::new();
+        let mut host = Vec::new();
+        let mut user = HashMap::new();
+        // main is 0
+        let mut bodies = vec![(&[][..], ins)];
+
+        for i in ins {
+            match i {
+                // not even loaded, check has already reported it
+                AsmIns::Include(lbl) if options.sandbox.as_ref().is_some_and(|s| !s.includes(lbl, &provider)) => { }
+                AsmIns::Include(lbl) => {
+                    provider.get_ptrs(&mut natives, lbl);
+                    host.extend(provider.host_functions(lbl));
+                    sigs.extend(provider.signatures(lbl).unwrap_or_default());
+                }
+                AsmIns::Function(name, params, body) => {
+                    user.insert(name.as_str(), bodies.len());
+                    bodies.push((params.as_slice(), body.as_slice()));
+                }
+                _ => { }
+            }
+        }
+
+        let (image, mut offsets) = match &options.sandbox {
+            Some(_) => asm::image(ins),
+            None => Default::default()
+        };
+        let mut strings = Vec::new();
+        if options.sandbox.is_none() {
+            for s in asm::strings(ins) {
+                let copy: Box<[u8]> = [s.as_bytes(), &[0]].concat().into();
+                offsets.insert(s.as_ptr() as usize, copy.as_ptr() as Word);
+                strings.push(copy);
+            }
+        }
+        let sandbox = options.sandbox.as_ref();
+        let env = Env { user, host: &host, natives, sigs, checked: options.checked, sandbox, offsets, fuel: options.fuel.is_some() };
+
+        let mut vars = Vec::new();
+        let positions = asm::positions(ins);
+        let functions = bodies.iter().enumerate().map(|(idx, (params, body))| {
+            let mut compiler = Compiler::new(params, body, &env);
+            for (i, at) in body.iter().enumerate().zip(&positions[idx]) {
+                compiler.at = *at;
+                compiler.ins(i.1, body.get(i.0 + 1));
+            }
+
+            if idx == 0 {
+                vars = compiler.slots.iter().map(|(name, reg)| (name.to_string(), *reg)).collect();
+            }
+            compiler.finish(params.len())
+        }).collect();
+
+        let memory = sandbox.map(|sandbox| (sandbox.memory as Word, image));
+
+        Bytecode { functions, host, vars, strings, checked: options.checked, memory, fuel: options.fuel, streams: None }
+    }
+
+    // main's variables start out as whatever is in `vars` and are left there afterwards,
+    // also when the program stops at an error it didn't catch,
+    // unsafe because the program can read and write any address
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn run(&mut self, vars: &mut HashMap<String, Word>) -> Result<(), Error> {
+        let mut regs = vec![0; self.functions[0].regs];
+        for (name, reg) in &self.vars {
+            regs[*reg as usize] = vars.get(name).copied().unwrap_or(0);
+        }
+
+        let mut streams = self.streams.take();
+        let mut run = || rba_runtime::with_context(|| {
+            for s in self.strings.iter().filter(|_| self.checked) {
+                rba_runtime::rba_allow(s.as_ptr() as Word, s.len() as Word);
+            }
+            if let Some((size, image)) = &self.memory {
+                rba_runtime::rba_sandbox(*size, image.as_ptr(), image.len() as Word);
+            }
+            if let Some(budget) = self.fuel {
+                rba_runtime::rba_refuel(budget);
+            }
+            self.call(&mut regs)
+        });
+        let (_, error) = match &mut streams {
+            Some(streams) => rba_runtime::with_streams(streams, run),
+            None => run()
+        };
+        self.streams = streams;
+
+        for (name, reg) in &self.vars {
+            vars.insert(name.clone(), regs[*reg as usize]);
+        }
+        error.map_or(Ok(()), Err)
+    }
+
+    // FNs don't recurse on the rust stack, a call pushes a frame and the registers of every frame
+    // live one after another in `stack`, main's first, with no more than MAX_DEPTH callers
+    unsafe fn call(&self, stack: &mut Vec<Word>) -> Word {
+        let (size, base) = match &self.memory {
+            Some((size, _)) => (*size, rba_runtime::rba_memory()),
+            None => (0, 0)
+        };
+        let fuel = rba_runtime::rba_fuel() as *mut Word;
+        // the callers, where they go on from, where their registers start and where the result goes
+        let mut frames: Vec<(&Function, usize, usize, Option<Reg>)> = Vec::new();
+        let mut f = &self.functions[0];
+        let mut fp = 0;
+        let mut pc = 0;
+
+        macro_rules! reg {
+            ($r:expr) => { stack[fp + $r as usize] };
+        }
+
+        macro_rules! get {
+            ($arg:expr) => {
+                match $arg {
+                    Arg::Reg(r) => reg!(r),
+                    Arg::Imm(w) => w
+                }
+            };
+        }
+
+        macro_rules! args {
+            ($args:expr, $buf:ident) => {
+                let mut $buf = [0; MAX_NATIVE_ARGS];
+                for (slot, arg) in $buf.iter_mut().zip($args.iter()) {
+                    *slot = get!(*arg);
+                }
+                let $buf = &$buf[..$args.len()];
+            };
+        }
+
+        // back to the caller, or out of main
+        macro_rules! ret {
+            ($value:expr) => {{
+                let value = $value;
+                match frames.pop() {
+                    Some((caller, next, caller_fp, out)) => {
+                        stack.truncate(fp);
+                        (f, pc, fp) = (caller, next, caller_fp);
+                        if let Some(d) = out { reg!(d) = value; }
+                        continue;
+                    }
+                    None => return value
+                }
+            }};
+        }
+
+        loop {
+            let Some(op) = f.code.get(pc) else { ret!(0) };
+            pc += 1;
+
+            match op {
+                Op::Mov(d, a) => { reg!(*d) = get!(*a); }
+                Op::Swap(a, b) => { stack.swap(fp + *a as usize, fp + *b as usize); }
+                Op::Add(d, a) => { reg!(*d) = reg!(*d).wrapping_add(get!(*a)); }
+                Op::Sub(d, a) => { reg!(*d) = reg!(*d).wrapping_sub(get!(*a)); }
+                Op::Mul(d, a) => { reg!(*d) = reg!(*d).wrapping_mul(get!(*a)); }
+                Op::Div(d, a) => { reg!(*d) /= get!(*a); }
+                Op::Mod(d, a) => { reg!(*d) %= get!(*a); }
+                Op::SDiv(d, a) => { reg!(*d) = (reg!(*d) as i64).wrapping_div(get!(*a) as i64) as Word; }
+                Op::SMod(d, a) => { reg!(*d) = (reg!(*d) as i64).wrapping_rem(get!(*a) as i64) as Word; }
+                Op::And(d, a) => { reg!(*d) &= get!(*a); }
+                Op::Or(d, a) => { reg!(*d) |= get!(*a); }
+                Op::Xor(d, a) => { reg!(*d) ^= get!(*a); }
+                // shift and rotate amounts wrap at 64, same as cranelift
+                Op::Shl(d, a) => { reg!(*d) = reg!(*d).wrapping_shl(get!(*a) as u32); }
+                Op::Shr(d, a) => { reg!(*d) = reg!(*d).wrapping_shr(get!(*a) as u32); }
+                Op::Sar(d, a) => { reg!(*d) = (reg!(*d) as i64).wrapping_shr(get!(*a) as u32) as Word; }
+                Op::Rol(d, a) => { reg!(*d) = reg!(*d).rotate_left(get!(*a) as u32); }
+                Op::Ror(d, a) => { reg!(*d) = reg!(*d).rotate_right(get!(*a) as u32); }
+                Op::Neg(d, a) => { reg!(*d) = get!(*a).wrapping_neg(); }
+                Op::Not(d, a) => { reg!(*d) = !get!(*a); }
+                Op::Popcnt(d, a) => { reg!(*d) = get!(*a).count_ones() as Word; }
+                Op::Clz(d, a) => { reg!(*d) = get!(*a).leading_zeros() as Word; }
+                Op::Ctz(d, a) => { reg!(*d) = get!(*a).trailing_zeros() as Word; }
+                // wildly unsafe, but language specification demands it
+                Op::Load(width, d, a) => {
+                    let addr = get!(*a) as usize;
+                    reg!(*d) = match width {
+                        Width::W8 => (addr as *const u8).read_unaligned() as Word,
+                        Width::W16 => (addr as *const u16).read_unaligned() as Word,
+                        Width::W32 => (addr as *const u32).read_unaligned() as Word,
+                        Width::W64 => (addr as *const u64).read_unaligned()
+                    };
+                }
+                Op::Store(width, a, v) => {
+                    let addr = get!(*a) as usize;
+                    let v = get!(*v);
+                    match width {
+                        Width::W8 => (addr as *mut u8).write_unaligned(v as u8),
+                        Width::W16 => (addr as *mut u16).write_unaligned(v as u16),
+                        Width::W32 => (addr as *mut u32).write_unaligned(v as u32),
+                        Width::W64 => (addr as *mut u64).write_unaligned(v)
+                    }
+                }
+                Op::Addr(d, a) => { reg!(*d) = base.wrapping_add(get!(*a)); }
+                Op::Jmp(to) => { pc = *to; }
+                Op::Jz(a, to) => { if get!(*a) == 0 { pc = *to; } }
+                Op::Jnz(a, to) => { if get!(*a) != 0 { pc = *to; } }
+                Op::JCmp(cmp, a, b, to) => { if cmp.holds(get!(*a), get!(*b)) { pc = *to; } }
+                Op::Out(a) => { rba_runtime::rba_printc(get!(*a)); }
+                Op::Call(callee, args, out) => {
+                    let callee = &self.functions[*callee];
+                    let next = stack.len();
+                    stack.resize(next + callee.regs, 0);
+                    for (i, arg) in args.iter().take(callee.params).enumerate() {
+                        stack[next + i] = get!(*arg);
+                    }
+
+                    frames.push((f, pc, fp, *out));
+                    (f, pc, fp) = (callee, 0, next);
+                }
+                Op::Host(h, args, out) => {
+                    args!(args, buf);
+                    let ret = self.host[*h].1.call(buf);
+                    if let Some(d) = out { reg!(*d) = ret; }
+                }
+                Op::Native(ptr, returns, args, out) => {
+                    args!(args, buf);
+                    let ret = call_native(*ptr, buf, *returns);
+                    if let Some(d) = out { reg!(*d) = ret; }
+                }
+                Op::Catch(d) => {
+                    reg!(*d) = rba_runtime::rba_take_error();
+                    // running out of fuel can't be caught
+                    if rba_runtime::rba_failed() != 0 {
+                        ret!(0);
+                    }
+                }
+                Op::Fuel(at) => {
+                    if *fuel == 0 {
+                        rba_runtime::rba_trap(Error::OUT_OF_FUEL, *at as Word, 0);
+                        ret!(0);
+                    }
+                    *fuel -= 1;
+                }
+                Op::Check(at) => {
+                    if rba_runtime::rba_failed() != 0 {
+                        rba_runtime::rba_locate(*at as Word);
+                        ret!(0);
+                    }
+                }
+                Op::Guard(guard, a, trap) => {
+                    let v = get!(*a);
+                    let code = match guard {
+                        Guard::Divisor if v == 0 => Error::DIV_ZERO,
+                        Guard::Memory(width) if rba_runtime::rba_check(v, width.bytes()) == 0 => Error::BAD_ADDRESS,
+                        Guard::Bounds(len) if v.checked_add(*len).is_none_or(|end| end > size) => Error::OUT_OF_BOUNDS,
+                        Guard::Range(len) if v.checked_add(get!(*len)).is_none_or(|end| end > size) => Error::OUT_OF_BOUNDS,
+                        Guard::Denied => Error::DENIED,
+                        Guard::Depth if frames.len() as Word >= MAX_DEPTH => Error::TOO_DEEP,
+                        _ => continue
+                    };
+
+                    match trap.caught {
+                        Some(to) => {
+                            reg!(f.error) = code;
+                            pc = to;
+                        }
+                        None => {
+                            rba_runtime::rba_trap(code, trap.at as Word, v);
+                            ret!(0);
+                        }
+                    }
+                }
+                Op::Ret(a) => { ret!(get!(*a)); }
+            }
+        }
+    }
+}
+
+impl Redirect for Bytecode {
+    fn streams_mut(&mut self) -> &mut Option<Streams> {
+        &mut self.streams
+    }
+}
+
+// what every function gets compiled against
+struct Env<'a> {
+    user: HashMap<&'a str, usize>,
+    host: &'a [(String, HostFn)],
+    natives: HashMap<String, *const u8>,
+    sigs: HashMap<String, FnSig>,
+    checked: bool,
+    sandbox: Option<&'a Sandbox>,
+    // where the strings are, in a sandbox's memory or the copies the bytecode keeps
+    offsets: HashMap<usize, Word>,
+    fuel: bool
+}
+
+impl Env<'_> {
+    fn denies(&self, name: &str) -> bool {
+        self.sandbox.is_some_and(|s| s.denies(name, self.user.contains_key(name) || self.host.iter().any(|(n, _)| n == name)))
+    }
+}
+
+struct Compiler<'a> {
+    env: &'a Env<'a>,
+    slots: HashMap<&'a str, Reg>,
+    // the code of the last error JERR or errno caught
+    error: Reg,
+    // the instruction being compiled, and the guards in it to point past it when the next one catches its traps
+    at: usize,
+    caught: Option<Vec<usize>>,
+    // temporaries are only needed for one instruction, so they start over after the variables every time
+    named: Reg,
+    temp: Reg,
+    regs: Reg,
+    code: Vec<Op>,
+    labels: HashMap<&'a str, usize>,
+    jumps: Vec<(usize, &'a str)>
+}
+
+impl<'a> Compiler<'a> {
+    fn new(params: &'a [String], body: &'a [AsmIns], env: &'a Env<'a>) -> Compiler<'a> {
+        // parameters are where Op::Call puts the arguments, one after the other, a name given
+        // twice is the later one like on the jit (check doesn't let it through anyway)
+        let mut slots = HashMap::new();
+        for (i, name) in params.iter().enumerate() {
+            slots.insert(name.as_str(), i as Reg);
+        }
+        let mut next = params.len() as Reg;
+        for name in body.iter().flat_map(AsmIns::variables) {
+            slots.entry(name.as_str()).or_insert_with(|| { next += 1; next - 1 });
+        }
+
+        let error = next;
+        let named = error + 1;
+        Compiler {
+            env, slots, error, at: 0, caught: None, named, temp: named, regs: named,
+            code: Vec::new(), labels: HashMap::new(), jumps: Vec::new()
+        }
+    }
+
+    fn finish(mut self, params: usize) -> Function {
+        for (at, label) in self.jumps {
+            let target = *self.labels.get(label).unwrap_or_else(|| panic!("jump to undefined label `{label}`"));
+            match &mut self.code[at] {
+                Op::Jmp(to) | Op::Jz(_, to) | Op::Jnz(_, to) | Op::JCmp(_, _, _, to) => { *to = target; }
+                _ => unreachable!()
+            }
+        }
+
+        Function { code: self.code, params, regs: self.regs as usize, error: self.error }
+    }
+
+    fn temp(&mut self) -> Reg {
+        let reg = self.temp;
+        self.temp += 1;
+        self.regs = self.regs.max(self.temp);
+        reg
+    }
+
+    fn val(&mut self, v: &Val) -> Arg {
+        match v {
+            Val::Var(v) => Arg::Reg(self.load(v)),
+            Val::Const(Const::Word(w)) => Arg::Imm(*w),
+            Val::Const(Const::Str(s)) => Arg::Imm(self.env.offsets[&(s.as_ptr() as usize)])
+        }
+    }
+
+    // the register holding the variable's value, memory is loaded into a temporary
+    fn load(&mut self, v: &Var) -> Reg {
+        match v {
+            Var::Named(name) => self.slots[name.as_str()],
+            Var::Addr(addr, width) => {
+                let addr = self.val(addr);
+                if self.env.checked {
+                    self.guard(Guard::Memory(*width), addr);
+                }
+                let addr = self.address(addr, width.bytes());
+                let reg = self.temp();
+                self.code.push(Op::Load(*width, reg, addr));
+                reg
+            }
+        }
+    }
+
+    // where `len` bytes at `addr` really are, in a sandbox it's an offset that has to be inside its memory
+    fn address(&mut self, addr: Arg, len: Word) -> Arg {
+        self.range(addr, Guard::Bounds(len))
+    }
+
+    fn range(&mut self, addr: Arg, bounds: Guard) -> Arg {
+        if self.env.sandbox.is_none() {
+            return addr;
+        }
+
+        self.guard(bounds, addr);
+        let reg = self.temp();
+        self.code.push(Op::Addr(reg, addr));
+        Arg::Reg(reg)
+    }
+
+    // where a result meant for `v` goes before `set` puts it there
+    fn dest(&mut self, v: &Var) -> Reg {
+        match v {
+            Var::Named(name) => self.slots[name.as_str()],
+            Var::Addr(..) => self.temp()
+        }
+    }
+
+    // the address is worked out again, in case whatever came before changed it (same as the jit)
+    fn set(&mut self, v: &Var, to: Arg) {
+        match v {
+            Var::Named(name) => {
+                let reg = self.slots[name.as_str()];
+                if to != Arg::Reg(reg) {
+                    self.code.push(Op::Mov(reg, to));
+                }
+            }
+            Var::Addr(addr, width) => {
+                let addr = self.val(addr);
+                if self.env.checked {
+                    self.guard(Guard::Memory(*width), addr);
+                }
+                let addr = self.address(addr, width.bytes());
+                self.code.push(Op::Store(*width, addr, to));
+            }
+        }
+    }
+
+    fn jump(&mut self, op: Op, label: &'a str) {
+        self.jumps.push((self.code.len(), label));
+        self.code.push(op);
+    }
+
+    // after a call that can raise an error, either the next instruction catches it or the function returns
+    fn raised(&mut self, next: Option<&AsmIns>) {
+        self.code.push(if asm::catches(next) { Op::Catch(self.error) } else { Op::Check(self.at) });
+    }
+
+    fn guard(&mut self, guard: Guard, arg: Arg) {
+        if let Some(guards) = &mut self.caught {
+            guards.push(self.code.len());
+        }
+        self.code.push(Op::Guard(guard, arg, Trap { at: self.at, caught: None }));
+    }
+
+    // dividing by 0 traps, constants other than 0 don't need checking
+    fn divisor(&mut self, arg: Arg) {
+        if !matches!(arg, Arg::Imm(w) if w != 0) {
+            self.guard(Guard::Divisor, arg);
+        }
+    }
+
+    fn ins(&mut self, ins: &'a AsmIns, next: Option<&AsmIns>) {
+        if self.env.fuel && asm::burns(ins) {
+            self.code.push(Op::Fuel(self.at));
+        }
+
+        // the error JERR and errno see is this instruction's, 0 unless it traps or a call raises one
+        if asm::catches(next) && !asm::catches(Some(ins)) {
+            self.caught = Some(Vec::new());
+            self.code.push(Op::Mov(self.error, Arg::Imm(0)));
+        }
+
+        self.compile(ins, next);
+
+        for guard in self.caught.take().unwrap_or_default() {
+            let end = self.code.len();
+            if let Op::Guard(_, _, trap) = &mut self.code[guard] {
+                trap.caught = Some(end);
+            }
+        }
+    }
+
+    fn compile(&mut self, ins: &'a AsmIns, next: Option<&AsmIns>) {
+        self.temp = self.named;
+
+        match ins {
+            AsmIns::Move(val, var) => {
+                let v = self.val(val);
+                self.set(var, v);
+            }
+            AsmIns::Swap(Var::Named(a), Var::Named(b)) => {
+                let (a, b) = (self.slots[a.as_str()], self.slots[b.as_str()]);
+                self.code.push(Op::Swap(a, b));
+            }
+            AsmIns::Swap(v1, v2) => {
+                let (r1, r2) = (self.load(v1), self.load(v2));
+                let (t1, t2) = (self.temp(), self.temp());
+                self.code.push(Op::Mov(t1, Arg::Reg(r1)));
+                self.code.push(Op::Mov(t2, Arg::Reg(r2)));
+
+                self.set(v2, Arg::Reg(t1));
+                self.set(v1, Arg::Reg(t2));
+            }
+            AsmIns::Add(var, val) | AsmIns::Sub(var, val) | AsmIns::Mul(var, val) | AsmIns::Div(var, val)
+            | AsmIns::Mod(var, val) | AsmIns::SDiv(var, val) | AsmIns::SMod(var, val) | AsmIns::And(var, val)
+            | AsmIns::Or(var, val) | AsmIns::Xor(var, val) | AsmIns::Shl(var, val) | AsmIns::Shr(var, val)
+            | AsmIns::Sar(var, val) | AsmIns::Rol(var, val) | AsmIns::Ror(var, val) => {
+                let d = self.load(var);
+                let v = self.val(val);
+                if matches!(ins, AsmIns::Div(..) | AsmIns::Mod(..) | AsmIns::SDiv(..) | AsmIns::SMod(..)) {
+                    self.divisor(v);
+                }
+
+                self.code.push(match ins {
+                    AsmIns::Add(..) => Op::Add(d, v),
+                    AsmIns::Sub(..) => Op::Sub(d, v),
+                    AsmIns::Mul(..) => Op::Mul(d, v),
+                    AsmIns::Div(..) => Op::Div(d, v),
+                    AsmIns::Mod(..) => Op::Mod(d, v),
+                    AsmIns::SDiv(..) => Op::SDiv(d, v),
+                    AsmIns::SMod(..) => Op::SMod(d, v),
+                    AsmIns::And(..) => Op::And(d, v),
+                    AsmIns::Or(..) => Op::Or(d, v),
+                    AsmIns::Xor(..) => Op::Xor(d, v),
+                    AsmIns::Shl(..) => Op::Shl(d, v),
+                    AsmIns::Shr(..) => Op::Shr(d, v),
+                    AsmIns::Sar(..) => Op::Sar(d, v),
+                    AsmIns::Rol(..) => Op::Rol(d, v),
+                    _ => Op::Ror(d, v)
+                });
+                self.set(var, Arg::Reg(d));
+            }
+            AsmIns::Neg(var, val) | AsmIns::Not(var, val) | AsmIns::Popcnt(var, val) | AsmIns::Clz(var, val) | AsmIns::Ctz(var, val) => {
+                let v = self.val(val);
+                let d = self.dest(var);
+
+                self.code.push(match ins {
+                    AsmIns::Neg(..) => Op::Neg(d, v),
+                    AsmIns::Not(..) => Op::Not(d, v),
+                    AsmIns::Popcnt(..) => Op::Popcnt(d, v),
+                    AsmIns::Clz(..) => Op::Clz(d, v),
+                    _ => Op::Ctz(d, v)
+                });
+                self.set(var, Arg::Reg(d));
+            }
+            AsmIns::Label(name) => { self.labels.insert(name, self.code.len()); }
+            AsmIns::JZ(val, label) => {
+                let v = self.val(val);
+                self.jump(Op::Jz(v, 0), label);
+            }
+            AsmIns::JNz(val, label) => {
+                let v = self.val(val);
+                self.jump(Op::Jnz(v, 0), label);
+            }
+            AsmIns::Jmp(label) => { self.jump(Op::Jmp(0), label); }
+            AsmIns::JCmp(cmp, a, b, label) => {
+                let (a, b) = (self.val(a), self.val(b));
+                self.jump(Op::JCmp(*cmp, a, b, 0), label);
+            }
+            AsmIns::JErr(label) => { self.jump(Op::Jnz(Arg::Reg(self.error), 0), label); }
+            AsmIns::Output(val) => {
+                let v = self.val(val);
+                self.code.push(Op::Out(v));
+                self.raised(next);
+            }
+            AsmIns::Call(name, _, Some(out)) if name == ERRNO => { self.set(out, Arg::Reg(self.error)); }
+            AsmIns::Call(name, _, None) if name == ERRNO => { }
+            AsmIns::Call(name, _, _) if self.env.denies(name) => { self.guard(Guard::Denied, Arg::Imm(0)); }
+            AsmIns::Call(name, params, out) => {
+                // module functions get a sandbox's addresses as pointers, after checking that what
+                // they're going to touch is inside its memory, the Len after an Addr or else a byte
+                let own = self.env.user.contains_key(name.as_str()) || self.env.host.iter().any(|(n, _)| n == name);
+                let sig = self.env.sigs.get(name).copied().filter(|_| !own);
+                let mut args: Box<[Arg]> = params.iter().map(|p| self.val(p)).collect();
+                for idx in 0..args.len() {
+                    match sig {
+                        Some(sig) if sig.kind(idx) == Kind::Addr && sig.kind(idx + 1) == Kind::Len => {
+                            args[idx] = self.range(args[idx], Guard::Range(args[idx + 1]));
+                        }
+                        Some(sig) if sig.kind(idx) == Kind::Addr => { args[idx] = self.address(args[idx], 1); }
+                        _ => { }
+                    }
+                }
+                let d = out.as_ref().map(|out| self.dest(out));
+
+                let op = if let Some(&f) = self.env.user.get(name.as_str()) {
+                    self.guard(Guard::Depth, Arg::Imm(0));
+                    Op::Call(f, args, d)
+                } else if let Some(f) = self.env.host.iter().position(|(n, _)| n == name) {
+                    check_sig(name, self.env.host[f].1.sig, params.len(), out.is_some());
+                    Op::Host(f, args, d)
+                } else if let Some(&ptr) = self.env.natives.get(name).or(dynamic::c_function(name).as_ref()) {
+                    // C functions have nothing better to go off than the call
+                    let sig = self.env.sigs.get(name).copied().unwrap_or(FnSig::new(params.len(), out.is_some()));
+                    check_sig(name, sig, params.len(), out.is_some());
+                    Op::Native(ptr, sig.returns, args, d)
+                } else {
+                    panic!("unknown function `{name}`");
+                };
+
+                // C functions don't know about rba's errors
+                let fails = !matches!(op, Op::Native(..)) || self.env.sigs.contains_key(name) || name == "printc";
+
+                self.code.push(op);
+                if let (Some(out), Some(d)) = (out, d) {
+                    self.set(out, Arg::Reg(d));
+                }
+                if fails {
+                    self.raised(next);
+                }
+            }
+            AsmIns::Return(val) => {
+                let v = val.as_ref().map_or(Arg::Imm(0), |v| self.val(v));
+                self.code.push(Op::Ret(v));
+            }
+            AsmIns::Include(_) | AsmIns::Function(..) | AsmIns::TakeInput | AsmIns::CopyInput | AsmIns::Nop => { }
+        }
+    }
+}
+
+fn check_sig(name: &str, sig: FnSig, params: usize, out: bool) {
+    assert_eq!(sig.params, params, "`{name}` takes {} argument(s) but {params} were given", sig.params);
+    assert!(params <= MAX_NATIVE_ARGS, "`{name}` is given {params} arguments, at most {MAX_NATIVE_ARGS} are supported");
+    assert!(sig.returns || !out, "`{name}` does not return a value");
+}
+
+// calls a C ABI function taking `args.len()` Words, without a jit to make a trampoline
+// every number of arguments needs its own function pointer type
+unsafe fn call_native(ptr: *const u8, args: &[Word], returns: bool) -> Word {
+    macro_rules! call {
+        (@word $idx:literal) => { Word };
+        ($($idx:literal)*) => {
+            if returns {
+                std::mem::transmute::<*const u8, extern "C" fn($(call!(@word $idx)),*) -> Word>(ptr)($(args[$idx]),*)
+            } else {
+                std::mem::transmute::<*const u8, extern "C" fn($(call!(@word $idx)),*)>(ptr)($(args[$idx]),*);
+                0
+            }
+        };
+    }
+
+    match args.len() {
+        0 => call!(),
+        1 => call!(0),
+        2 => call!(0 1),
+        3 => call!(0 1 2),
+        4 => call!(0 1 2 3),
+        5 => call!(0 1 2 3 4),
+        6 => call!(0 1 2 3 4 5),
+        7 => call!(0 1 2 3 4 5 6),
+        8 => call!(0 1 2 3 4 5 6 7),
+        9 => call!(0 1 2 3 4 5 6 7 8),
+        10 => call!(0 1 2 3 4 5 6 7 8 9),
+        11 => call!(0 1 2 3 4 5 6 7 8 9 10),
+        12 => call!(0 1 2 3 4 5 6 7 8 9 10 11),
+        13 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12),
+        14 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13),
+        15 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14),
+        16 => call!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15),
+        n => panic!("native functions take at most {MAX_NATIVE_ARGS} arguments, not {n}")
+    }
+}
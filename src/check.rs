@@ -0,0 +1,341 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::asm::{self, AsmIns, Const, Val, Word, ERRNO};
+use crate::bytecode::MAX_NATIVE_ARGS;
+use crate::diagnostic::Diagnostic;
+use crate::modules::{FnSig, Host, Kind, Module, ModuleProvider};
+use rba_runtime::{Std, IO};
+use crate::parser::statement;
+use crate::program::Sandbox;
+
+// functions every program can call without an INC
+const BUILTINS: &[(&str, FnSig)] = &[
+    ("printc", FnSig { params: 1, returns: false, kinds: &[Kind::Word] }),
+    ("malloc", FnSig { params: 1, returns: true, kinds: &[Kind::Word] }),
+    ("atol", FnSig { params: 1, returns: true, kinds: &[Kind::Addr] }),
+    (ERRNO, FnSig { params: 0, returns: true, kinds: &[] }),
+];
+
+// instructions that run together, main or a single function body, paired with where they came from
+type Scope<'a> = Vec<(&'a AsmIns, Range<usize>)>;
+
+// looks over a parsed program for mistakes the backends would only find by crashing,
+// `spans` has the source range of every instruction as given by `parser::asm_spanned`
+pub fn check(ins: &[AsmIns], spans: &[Range<usize>], src: &[u8], provider: &impl ModuleProvider,
+             sandbox: Option<&Sandbox>) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    let at = |span: &Range<usize>, message: String| {
+        Diagnostic::new(src, span.start, span.len(), message).instruction(statement(src, span.start))
+    };
+
+    let scopes = scopes(ins, spans);
+
+    for scope in &scopes {
+        check_labels(scope, &at, &mut diags);
+        check_reachable(scope, &at, &mut diags);
+        check_divisors(scope, &at, &mut diags);
+    }
+    check_calls(&scopes, provider, sandbox, &at, &mut diags);
+    if let Some(sandbox) = sandbox {
+        check_sandbox(&scopes, provider, sandbox, &at, &mut diags);
+        check_memory(ins, &scopes, sandbox, &at, &mut diags);
+    }
+
+    diags.sort_by_key(|d| d.offset);
+    diags
+}
+
+// compiled ahead of time the program is linked with the runtime library, so only its modules are there to INC
+pub(crate) fn check_object(ins: &[AsmIns], spans: &[Range<usize>], src: &[u8]) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    for (i, span) in &scopes(ins, spans)[0] {
+        let AsmIns::Include(module) = i else { continue };
+        let note = match module.as_str() {
+            Std::NAME | IO::NAME => continue,
+            <Host>::NAME => "host closures only exist in the process that registered them, run it with the jit or the interpreter",
+            _ => "shared libraries aren't linked into executables, run it with the jit or the interpreter"
+        };
+        diags.push(Diagnostic::new(src, span.start, span.len(), format!("module `{module}` can't be compiled ahead of time"))
+            .instruction(statement(src, span.start)).note(note));
+    }
+
+    diags
+}
+
+fn scopes<'a>(ins: &'a [AsmIns], spans: &[Range<usize>]) -> Vec<Scope<'a>> {
+    let mut spans = spans.iter().cloned();
+    let mut main = Vec::new();
+    let mut bodies = Vec::new();
+
+    for i in ins {
+        main.push((i, spans.next().unwrap_or_default()));
+
+        if let AsmIns::Function(_, _, body) = i {
+            bodies.push(body.iter().map(|b| (b, spans.next().unwrap_or_default())).collect());
+        }
+    }
+
+    std::iter::once(main).chain(bodies).collect()
+}
+
+// labels belong to the scope they are in, a function can't jump into main
+fn check_labels(scope: &Scope, at: &impl Fn(&Range<usize>, String) -> Diagnostic, diags: &mut Vec<Diagnostic>) {
+    let mut labels = HashMap::new();
+
+    for (i, span) in scope {
+        if let AsmIns::Label(lbl) = i {
+            if lbl.is_empty() {
+                diags.push(at(span, String::from("label has no name")));
+            } else if let Some(first) = labels.get(lbl) {
+                diags.push(at(span, format!("label `{lbl}` is defined more than once"))
+                    .note(format!("first defined on line {}", at(first, String::new()).line)));
+            } else {
+                labels.insert(lbl, span.clone());
+            }
+        }
+    }
+
+    for (i, span) in scope {
+        match i {
+            AsmIns::JZ(_, lbl) | AsmIns::JNz(_, lbl) | AsmIns::Jmp(lbl) | AsmIns::JCmp(_, _, _, lbl) | AsmIns::JErr(lbl)
+                if !labels.contains_key(lbl) => {
+                diags.push(at(span, format!("jump to undefined label `{lbl}`")));
+            }
+            _ => { }
+        }
+    }
+}
+
+fn check_calls(scopes: &[Scope], provider: &impl ModuleProvider, sandbox: Option<&Sandbox>, at: &impl Fn(&Range<usize>, String) -> Diagnostic,
+               diags: &mut Vec<Diagnostic>) {
+    let main = &scopes[0];
+    let mut included = HashMap::new();
+    let mut elsewhere = HashMap::new();
+    // what a sandbox doesn't let in isn't loaded to look at, check_sandbox reports it
+    let loadable = |module: &str| sandbox.is_none_or(|s| s.includes(module, provider));
+
+    // not everything that can be included is listed, shared libraries are only found once asked for
+    for (i, _) in main {
+        if let AsmIns::Include(module) = i {
+            if !loadable(module) {
+                continue;
+            }
+            for (name, sig) in provider.signatures(module).unwrap_or_default() {
+                included.insert(name, (sig, module.clone()));
+            }
+        }
+    }
+
+    for module in provider.modules() {
+        if main.iter().any(|(i, _)| matches!(i, AsmIns::Include(lbl) if *lbl == module)) {
+            continue;
+        }
+
+        for (name, _) in provider.signatures(&module).unwrap_or_default() {
+            elsewhere.insert(name, module.clone());
+        }
+    }
+
+    let mut user: HashMap<&String, (FnSig, Range<usize>)> = HashMap::new();
+
+    for (i, span) in main {
+        if let AsmIns::Function(name, params, _) = i {
+            // the second one would have nowhere to go, the arguments are passed by position
+            for (idx, param) in params.iter().enumerate() {
+                if params[..idx].contains(param) {
+                    diags.push(at(span, format!("parameter `{param}` of `{name}` is named more than once")));
+                }
+            }
+
+            if name == "main" {
+                diags.push(at(span, String::from("`main` is reserved for the top level of the program")));
+            } else if name == ERRNO {
+                diags.push(at(span, format!("`{ERRNO}` is reserved for the error of the call before it")));
+            } else if let Some((_, first)) = user.get(name) {
+                diags.push(at(span, format!("function `{name}` is defined more than once"))
+                    .note(format!("first defined on line {}", at(first, String::new()).line)));
+            } else if let Some((_, module)) = included.get(name) {
+                diags.push(at(span, format!("function `{name}` is already defined by module `{module}`")));
+            } else {
+                user.insert(name, (FnSig::new(params.len(), true), span.clone()));
+            }
+        }
+    }
+
+    // C functions have no signature to go off, but every call has to agree with the first one
+    let mut seen: HashMap<&String, (Range<usize>, FnSig)> = HashMap::new();
+
+    for (idx, scope) in scopes.iter().enumerate() {
+        for (i, span) in scope {
+            match i {
+                AsmIns::Include(_) if idx != 0 => {
+                    diags.push(at(span, String::from("`INC` can only be used outside of functions")));
+                }
+                AsmIns::Include(lbl) if loadable(lbl) && provider.signatures(lbl).is_none() => {
+                    let mut diag = at(span, format!("unknown module `{lbl}`")).expected(provider.modules());
+                    if let Some(e) = provider.load_error(lbl) {
+                        diag = diag.note(e);
+                    }
+                    diags.push(diag);
+                }
+                AsmIns::Call(name, params, out) => {
+                    let used = FnSig::new(params.len(), out.is_some());
+                    if !user.contains_key(name) && params.len() > MAX_NATIVE_ARGS {
+                        diags.push(at(span, format!("`{name}` is given {} arguments, module and C functions can take at most {MAX_NATIVE_ARGS}", params.len())));
+                        continue;
+                    }
+
+                    let known = user.get(name).map(|(sig, _)| *sig)
+                        .or_else(|| included.get(name).map(|(sig, _)| *sig))
+                        .or_else(|| BUILTINS.iter().find(|(n, _)| n == name).map(|(_, s)| *s));
+
+                    if let Some(sig) = known {
+                        if sig.params != used.params {
+                            diags.push(at(span, format!("`{name}` takes {} argument(s) but {} were given", sig.params, used.params)));
+                        } else if used.returns && !sig.returns {
+                            diags.push(at(span, format!("`{name}` does not return a value")));
+                        } else {
+                            check_kinds(name, &sig, params, sandbox.is_some(), &|message| at(span, message), diags);
+                        }
+                    } else if let Some(module) = elsewhere.get(name) {
+                        diags.push(at(span, format!("`{name}` is from module `{module}`, which is not included"))
+                            .note(format!("add `INC {module};` to the top of the program")));
+                    } else if !c_symbol_exists(name) {
+                        diags.push(at(span, format!("unknown function `{name}`")));
+                    } else if let Some((first, sig)) = seen.get(name) {
+                        if *sig != used {
+                            diags.push(at(span, format!("`{name}` is called differently than before"))
+                                .note(format!("first called as `{}`", at(first, String::new()).instruction.unwrap_or_default())));
+                        }
+                    } else {
+                        seen.insert(name, (span.clone(), used));
+                    }
+                }
+                _ => { }
+            }
+        }
+    }
+}
+
+// constants that can't be what a parameter wants, a variable could hold anything
+fn check_kinds(name: &str, sig: &FnSig, params: &[Val], sandboxed: bool, at: &impl Fn(String) -> Diagnostic,
+               diags: &mut Vec<Diagnostic>) {
+    for (idx, param) in params.iter().enumerate() {
+        let nth = idx + 1;
+        match (sig.kind(idx), param) {
+            (Kind::Wp, Val::Const(Const::Str(_))) => {
+                diags.push(at(format!("argument {nth} of `{name}` is a handle, not a string"))
+                    .note("handles come from `stdout`, `stderr`, `stdin`, `open_file`, `open_read`, `open_append`, `open_rw`, `open_fd` and `dir_open`"));
+            }
+            (Kind::Word | Kind::Len, Val::Const(Const::Str(_))) => {
+                diags.push(at(format!("argument {nth} of `{name}` is a number, this passes the string's address")).warning());
+            }
+            // a sandbox's addresses are small offsets, any number can be one
+            (Kind::Addr, Val::Const(Const::Word(w))) if *w != 0 && !sandboxed => {
+                diags.push(at(format!("argument {nth} of `{name}` is an address, `{w}` is unlikely to be one")).warning());
+            }
+            _ => { }
+        }
+    }
+}
+
+// code right after a RET or an always taken jump can only run if something jumps to a label below it
+fn check_reachable(scope: &Scope, at: &impl Fn(&Range<usize>, String) -> Diagnostic, diags: &mut Vec<Diagnostic>) {
+    let mut reachable = true;
+
+    for (i, span) in scope {
+        match i {
+            AsmIns::Label(_) => { reachable = true; }
+            AsmIns::Nop | AsmIns::Include(_) | AsmIns::Function(..) => { }
+            _ if !reachable => {
+                diags.push(at(span, String::from("unreachable code")).warning()
+                    .note("nothing jumps here and the instruction before it never falls through"));
+                reachable = true;
+            }
+            AsmIns::JZ(Val::Const(Const::Word(0)), _) => { reachable = false; }
+            AsmIns::JNz(Val::Const(Const::Word(w)), _) if *w != 0 => { reachable = false; }
+            AsmIns::Jmp(_) | AsmIns::Return(_) => { reachable = false; }
+            _ => { }
+        }
+    }
+}
+
+// a sandboxed program can only call its own functions, host closures and what the sandbox allows,
+// and only include modules that don't have to be loaded from a file
+fn check_sandbox(scopes: &[Scope], provider: &impl ModuleProvider, sandbox: &Sandbox, at: &impl Fn(&Range<usize>, String) -> Diagnostic,
+                 diags: &mut Vec<Diagnostic>) {
+    let mut own = HashSet::new();
+    for (i, span) in &scopes[0] {
+        match i {
+            AsmIns::Function(name, _, _) => { own.insert(name.clone()); }
+            AsmIns::Include(module) if !sandbox.includes(module, provider) => {
+                diags.push(at(span, format!("the sandbox doesn't allow including `{module}`"))
+                    .expected(provider.modules())
+                    .note("only the provider's own modules and what the sandbox allows can be included"));
+            }
+            AsmIns::Include(module) => { own.extend(provider.host_functions(module).into_iter().map(|(name, _)| name)); }
+            _ => { }
+        }
+    }
+
+    for (i, span) in scopes.iter().flatten() {
+        if let AsmIns::Call(name, _, _) = i {
+            if sandbox.denies(name, own.contains(name)) {
+                diags.push(at(span, format!("the sandbox doesn't allow calling `{name}`"))
+                    .note("only the program's own functions, host functions, `malloc`, `free` and what the sandbox allows can be called"));
+            }
+        }
+    }
+}
+
+// a sandbox's memory starts out with nothing where 0 points and then the strings, they all have to fit
+fn check_memory(ins: &[AsmIns], scopes: &[Scope], sandbox: &Sandbox, at: &impl Fn(&Range<usize>, String) -> Diagnostic,
+                diags: &mut Vec<Diagnostic>) {
+    let memory = sandbox.memory as Word;
+    let needed = rba_runtime::SANDBOX_NULL + asm::strings(ins).iter().map(|s| s.len() as Word + 1).sum::<Word>();
+    if needed <= memory {
+        return;
+    }
+
+    // points at the first string that doesn't fit (function bodies are scopes of their own so a FN is
+    // skipped), or the start of the program when not even the empty bytes do
+    let mut used = rba_runtime::SANDBOX_NULL;
+    let mut seen = HashSet::new();
+    let mut span = 0..0;
+    for (i, first) in scopes.iter().flatten().filter(|(i, _)| !matches!(i, AsmIns::Function(..))) {
+        if used > memory {
+            break;
+        }
+        for string in asm::strings(std::slice::from_ref(*i)) {
+            if seen.insert(string.as_ptr()) {
+                used += string.len() as Word + 1;
+            }
+        }
+        if used > memory {
+            span = first.clone();
+        }
+    }
+
+    diags.push(at(&span, format!("the sandbox's {memory} bytes of memory are too small for the program's strings, they need {needed}"))
+        .note(format!("the first {} bytes are kept empty so no string is at 0", rba_runtime::SANDBOX_NULL)));
+}
+
+// dividing by a constant 0 always traps
+fn check_divisors(scope: &Scope, at: &impl Fn(&Range<usize>, String) -> Diagnostic, diags: &mut Vec<Diagnostic>) {
+    for (i, span) in scope {
+        if let AsmIns::Div(_, Val::Const(Const::Word(0))) | AsmIns::Mod(_, Val::Const(Const::Word(0)))
+            | AsmIns::SDiv(_, Val::Const(Const::Word(0))) | AsmIns::SMod(_, Val::Const(Const::Word(0))) = i {
+            diags.push(at(span, String::from("division by zero")).warning()
+                .note("this always stops the program unless the instruction after it is `JERR` or `CALL errno`"));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn c_symbol_exists(name: &str) -> bool {
+    crate::dynamic::c_function(name).is_some()
+}
+
+// no cheap way to ask on other platforms, leave it to the linker
+#[cfg(not(unix))]
+fn c_symbol_exists(_: &str) -> bool { true }
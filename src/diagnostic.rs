@@ -0,0 +1,109 @@
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+// a problem found in a source file, remembers enough of the source to point at it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+    pub expected: Vec<String>,
+    pub instruction: Option<String>,
+    pub notes: Vec<String>,
+    snippet: String,
+}
+
+impl Diagnostic {
+    pub fn new(src: &[u8], offset: usize, len: usize, message: impl Into<String>) -> Diagnostic {
+        let offset = offset.min(src.len());
+        let start = src[..offset].iter().rposition(|&c| c == b'\n').map_or(0, |p| p + 1);
+        let end = src[offset..].iter().position(|&c| c == b'\n').map_or(src.len(), |p| offset + p);
+
+        let line = src[..start].iter().filter(|&&c| c == b'\n').count() + 1;
+        let column = String::from_utf8_lossy(&src[start..offset]).chars().count() + 1;
+        let snippet = String::from_utf8_lossy(&src[start..end]).trim_end().to_string();
+
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            offset,
+            line,
+            column,
+            len: len.max(1),
+            expected: Vec::new(),
+            instruction: None,
+            notes: Vec::new(),
+            snippet,
+        }
+    }
+
+    pub fn expected<S: Into<String>>(mut self, expected: impl IntoIterator<Item = S>) -> Diagnostic {
+        self.expected.extend(expected.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn instruction(mut self, ins: impl Into<String>) -> Diagnostic {
+        self.instruction = Some(ins.into());
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn warning(mut self) -> Diagnostic {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let num = self.line.to_string();
+        let pad = " ".repeat(num.len());
+
+        // tabs would throw the caret off, so print them as single spaces
+        let snippet = self.snippet.replace('\t', " ");
+        let width = snippet.chars().count().saturating_sub(self.column - 1).max(1);
+
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+
+        writeln!(f, "{severity}: {}", self.message)?;
+        writeln!(f, "{pad}--> {}:{}", self.line, self.column)?;
+        writeln!(f, "{pad} |")?;
+        writeln!(f, "{num} | {snippet}")?;
+        write!(f, "{pad} | {}{}", " ".repeat(self.column - 1), "^".repeat(self.len.min(width)))?;
+
+        if !self.expected.is_empty() {
+            let expected: Vec<_> = self.expected.iter().map(|e| format!("`{e}`")).collect();
+            let one_of = if expected.len() > 1 { " one of" } else { "" };
+            write!(f, "\n{pad} = expected{one_of}: {}", expected.join(", "))?;
+        }
+        if let Some(ins) = &self.instruction {
+            write!(f, "\n{pad} = in instruction: `{ins}`")?;
+        }
+        for note in &self.notes {
+            write!(f, "\n{pad} = note: {note}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic { }
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::env::{self, consts};
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use rba_runtime::{FnSig, ModuleDescriptor, ABI_VERSION};
+
+// a module from a shared library, the library is never unloaded so the functions stay valid
+pub struct Dynamic {
+    pub name: String,
+    pub path: PathBuf,
+    pub functions: Vec<(String, FnSig, usize)>
+}
+
+impl Dynamic {
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, *const u8)> {
+        self.functions.iter().map(|(name, _, ptr)| (name.as_str(), *ptr as *const u8))
+    }
+
+    pub fn signatures(&self) -> Vec<(String, FnSig)> {
+        self.functions.iter().map(|(name, sig, _)| (name.clone(), *sig)).collect()
+    }
+}
+
+// by what was INCed, failures are kept too so a missing module is only looked for once
+static LOADED: Mutex<Option<HashMap<String, Result<&'static Dynamic, String>>>> = Mutex::new(None);
+
+// `module` is either a path to a shared library or a name to look for in $RBA_MODULE_PATH,
+// libfoo.so (foo.dll, libfoo.dylib) for `foo`, the working directory is only searched if it's listed
+pub fn load(module: &str) -> Result<&'static Dynamic, String> {
+    let mut loaded = LOADED.lock().unwrap();
+    loaded.get_or_insert_with(HashMap::new)
+        .entry(module.to_string())
+        .or_insert_with(|| find(module).and_then(|path| open(&path)).map(|d| &*Box::leak(Box::new(d))))
+        .clone()
+}
+
+fn find(module: &str) -> Result<PathBuf, String> {
+    // made absolute, dlopen would look a bare file name up in the system's directories
+    if module.contains(['/', '\\']) || module.ends_with(consts::DLL_SUFFIX) {
+        return Path::new(module).canonicalize().map_err(|e| format!("can't load `{module}`: {e}"));
+    }
+
+    let file = format!("{}{module}{}", consts::DLL_PREFIX, consts::DLL_SUFFIX);
+    // an empty entry would be the working directory too
+    let dirs: Vec<PathBuf> = env::var_os("RBA_MODULE_PATH")
+        .map(|p| env::split_paths(&p).filter(|d| !d.as_os_str().is_empty()).collect()).unwrap_or_default();
+    if dirs.is_empty() {
+        return Err(format!("RBA_MODULE_PATH isn't set so there's nowhere to look for `{file}`, INC a path to load it from there"));
+    }
+
+    dirs.iter().find_map(|dir| dir.join(&file).canonicalize().ok()).ok_or_else(|| {
+        let dirs: Vec<_> = dirs.iter().map(|d| d.display().to_string()).collect();
+        format!("no `{file}` in {}, RBA_MODULE_PATH can list more directories to look in", dirs.join(", "))
+    })
+}
+
+fn open(path: &Path) -> Result<Dynamic, String> {
+    // libfoo.so exports foo
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let name = stem.strip_prefix(consts::DLL_PREFIX).unwrap_or(stem);
+
+    let symbol = format!("rba_module_{name}");
+    let descriptor = unsafe { library::symbol(path, &symbol)? } as *const ModuleDescriptor;
+    let descriptor = unsafe { &*descriptor };
+
+    if descriptor.abi != ABI_VERSION {
+        return Err(format!("`{}` was built for a different version of rba (abi {}, expected {ABI_VERSION})",
+                           path.display(), descriptor.abi));
+    }
+    (descriptor.forward)(rba_runtime::rba_raise);
+
+    let functions = unsafe { std::slice::from_raw_parts(descriptor.functions, descriptor.len) };
+    let functions = functions.iter().map(|f| {
+        let name = unsafe { CStr::from_ptr(f.name) }.to_string_lossy().into_owned();
+        let kinds = unsafe { std::slice::from_raw_parts(f.kinds, f.params) };
+        let sig = FnSig { params: f.params, returns: f.returns, kinds: Box::leak(kinds.into()) };
+        (name, sig, f.ptr as usize)
+    }).collect();
+
+    Ok(Dynamic { name: name.to_string(), path: path.to_path_buf(), functions })
+}
+
+// a function already linked into the process, libc and the like
+pub fn c_function(name: &str) -> Option<*const u8> {
+    library::lookup(name)
+}
+
+#[cfg(unix)]
+mod library {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    // opens the library (for good) and looks `symbol` up in it
+    pub unsafe fn symbol(path: &Path, symbol: &str) -> Result<*const u8, String> {
+        let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+        let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if handle.is_null() {
+            return Err(format!("can't load `{}`: {}", path.display(), error()));
+        }
+
+        let c_symbol = CString::new(symbol).unwrap();
+        let ptr = libc::dlsym(handle, c_symbol.as_ptr());
+        if ptr.is_null() {
+            return Err(format!("`{}` is not an rba module, it has no `{symbol}`", path.display()));
+        }
+
+        Ok(ptr as *const u8)
+    }
+
+    pub fn lookup(name: &str) -> Option<*const u8> {
+        let name = CString::new(name).ok()?;
+        let ptr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+        (!ptr.is_null()).then_some(ptr as *const u8)
+    }
+
+    unsafe fn error() -> String {
+        let err = libc::dlerror();
+        if err.is_null() { String::from("unknown error") } else { CStr::from_ptr(err).to_string_lossy().into_owned() }
+    }
+}
+
+#[cfg(not(unix))]
+mod library {
+    use std::path::Path;
+
+    pub unsafe fn symbol(path: &Path, _: &str) -> Result<*const u8, String> {
+        Err(format!("can't load `{}`, modules from shared libraries only work on unix for now", path.display()))
+    }
+
+    pub fn lookup(_: &str) -> Option<*const u8> {
+        None
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use cranelift::prelude::*;
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use crate::asm::{self, AsmIns, Linking, Word};
+use crate::modules::{rba_host_call, HostFn, ModuleProvider};
+use crate::program::Program;
+use rba_runtime::{Error, Streams};
+
+// where OUT and io's stdout, stderr and stdin go for every run after, for the engine and
+// the interpreter's bytecode alike, a `Capture` can be read back
+pub trait Redirect {
+    // none set leaves the streams as they are, so `with_streams` around a run still works
+    fn streams_mut(&mut self) -> &mut Option<Streams>;
+
+    fn set_stdout(&mut self, stdout: impl Write + 'static) -> &mut Self {
+        self.streams_mut().get_or_insert_with(Streams::default).stdout = Box::new(stdout);
+        self
+    }
+
+    fn set_stderr(&mut self, stderr: impl Write + 'static) -> &mut Self {
+        self.streams_mut().get_or_insert_with(Streams::default).stderr = Box::new(stderr);
+        self
+    }
+
+    fn set_stdin(&mut self, stdin: impl Read + 'static) -> &mut Self {
+        self.streams_mut().get_or_insert_with(Streams::default).stdin = Box::new(stdin);
+        self
+    }
+}
+
+// a program compiled by the jit, holds on to the code and main's variables between runs
+pub struct Engine {
+    // only None while dropping
+    module: Option<JITModule>,
+    main: unsafe extern "C" fn(*mut Word),
+    names: Vec<String>,
+    values: Vec<Word>,
+    // the line of every instruction, to say where an error happened
+    lines: Vec<Option<usize>>,
+    streams: Option<Streams>,
+    // the compiled code points into these, boxed so they stay put wherever the vec goes
+    #[allow(clippy::vec_box)]
+    _host: Vec<Box<HostFn>>
+}
+
+impl Engine {
+    pub(crate) fn new(program: &Program, provider: impl ModuleProvider) -> Engine {
+        let ins = program.instructions();
+
+        let mut builder = JITBuilder::with_isa(asm::isa(), default_libcall_names());
+        builder.symbol("printc", rba_runtime::rba_printc as *const u8);
+        builder.symbol("rba_host_call", rba_host_call as *const u8);
+        builder.symbol("rba_failed", rba_runtime::rba_failed as *const u8);
+        builder.symbol("rba_take_error", rba_runtime::rba_take_error as *const u8);
+        builder.symbol("rba_locate", rba_runtime::rba_locate as *const u8);
+        builder.symbol("rba_trap", rba_runtime::rba_trap as *const u8);
+        builder.symbol("rba_check", rba_runtime::rba_check as *const u8);
+        builder.symbol("rba_allow", rba_runtime::rba_allow as *const u8);
+        builder.symbol("rba_malloc", rba_runtime::rba_malloc as *const u8);
+        builder.symbol("rba_free", rba_runtime::rba_free as *const u8);
+        builder.symbol("rba_sandbox", rba_runtime::rba_sandbox as *const u8);
+        builder.symbol("rba_memory", rba_runtime::rba_memory as *const u8);
+        builder.symbol("rba_sandbox_malloc", rba_runtime::rba_sandbox_malloc as *const u8);
+        builder.symbol("rba_sandbox_free", rba_runtime::rba_sandbox_free as *const u8);
+        builder.symbol("rba_refuel", rba_runtime::rba_refuel as *const u8);
+        builder.symbol("rba_fuel", rba_runtime::rba_fuel as *const u8);
+
+        let mut host = Vec::new();
+        let mut signatures = HashMap::new();
+        for i in ins {
+            if let AsmIns::Include(lib) = i {
+                // the check pass reports it, but a module the sandbox doesn't let in is never loaded
+                if program.options().sandbox.as_ref().is_some_and(|s| !s.includes(lib, &provider)) {
+                    continue;
+                }
+                provider.add_functions(&mut builder, lib);
+                host.extend(provider.host_functions(lib).into_iter().map(|(name, f)| (name, Box::new(f))));
+                signatures.extend(provider.signatures(lib).unwrap_or_default());
+            }
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        for v in ins.iter().flat_map(AsmIns::variables) {
+            if !names.contains(v) {
+                names.push(v.clone());
+            }
+        }
+
+        let mut module = JITModule::new(builder);
+        let mut sig_main = module.make_signature();
+        sig_main.params.push(AbiParam::new(types::I64));
+
+        let mut linking = Linking {
+            state: Some(names.clone()),
+            host: host.iter().map(|(name, f)| (name.clone(), &**f as *const HostFn)).collect(),
+            signatures,
+            checked: program.options().checked,
+            sandbox: program.options().sandbox.clone(),
+            fuel: program.options().fuel,
+            ..Linking::default()
+        };
+        for (name, symbol, _) in program.options().allocator() {
+            linking.symbols.insert(name.to_string(), symbol.to_string());
+        }
+        let func_main = asm::compile(&mut module, ins, sig_main, &mut linking);
+        module.finalize_definitions().unwrap();
+
+        let code = module.get_finalized_function(func_main);
+        let main = unsafe { std::mem::transmute::<*const u8, unsafe extern "C" fn(*mut Word)>(code) };
+
+        let host = host.into_iter().map(|(_, f)| f).collect();
+        let count = asm::positions(ins).iter().map(Vec::len).sum();
+        let lines = (0..count).map(|at| program.line(at)).collect();
+
+        Engine { module: Some(module), main, values: vec![0; names.len()], names, lines, streams: None, _host: host }
+    }
+
+    // runs main, the variables carry over from whatever was set or left by the last run (even one
+    // that stopped at an error the program didn't catch),
+    // unsafe because the program can read and write any address
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn run(&mut self) -> Result<(), Error> {
+        let (main, values) = (self.main, self.values.as_mut_ptr());
+        let run = || rba_runtime::with_context(|| main(values));
+        let ((), error) = match &mut self.streams {
+            Some(streams) => rba_runtime::with_streams(streams, run),
+            None => run()
+        };
+        match error {
+            Some(error) => {
+                let line = error.at.and_then(|at| self.lines.get(at).copied().flatten());
+                Err(Error { line, ..error })
+            }
+            None => Ok(())
+        }
+    }
+
+    // None if main never uses `name`
+    pub fn get(&self, name: &str) -> Option<Word> {
+        self.names.iter().position(|n| n == name).map(|idx| self.values[idx])
+    }
+
+    // false if main never uses `name`, there is nowhere to put it
+    pub fn set(&mut self, name: &str, value: Word) -> bool {
+        match self.names.iter().position(|n| n == name) {
+            Some(idx) => {
+                self.values[idx] = value;
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = (&str, Word)> {
+        self.names.iter().map(String::as_str).zip(self.values.iter().copied())
+    }
+}
+
+impl Redirect for Engine {
+    fn streams_mut(&mut self) -> &mut Option<Streams> {
+        &mut self.streams
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        // nothing can call into the code anymore
+        unsafe { self.module.take().unwrap().free_memory() };
+    }
+}
@@ -0,0 +1,20 @@
+// building up layers of abstraction to go from nothing to
+// high level language, microprocessor/asm to python level
+
+pub mod aot;
+pub mod asm;
+pub mod bytecode;
+pub mod check;
+pub mod diagnostic;
+pub mod dynamic;
+pub mod engine;
+pub mod modules;
+pub mod parser;
+pub mod program;
+
+pub use asm::Word;
+pub use diagnostic::Diagnostic;
+pub use engine::{Engine, Redirect};
+pub use modules::Host;
+pub use program::{Options, Program, Sandbox};
+pub use rba_runtime::{with_streams, Capture, Error, Streams, MAX_DEPTH};
@@ -1,37 +1,255 @@
-// building up layers of abstraction to go from nothing to
-// high level language, microprocessor/asm to python level
-
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::exit;
 use std::time::Instant;
-use crate::parser::{asm, fcall};
-use crate::asm::{AsmIns as Ins, Var, Val};
-use crate::modules::{BorrowingModuleProvider, DefaultModuleProvider};
+use rba::aot;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Diagnostic, Program, Sandbox, Word};
+
+const USAGE: &str = "usage: rba [command] [options] <file>
+
+commands:
+  run        checks and runs the program (default)
+  check      only reports problems with the program
+  dump-ast   prints the parsed instructions
+  dump-ir    prints the cranelift ir of main and every function
+  build      compiles the program ahead of time, needs -o
+
+options:
+  --backend <jit|interp>  what `run` executes the program with, jit by default
+  -o <out>                where `build` writes to, an executable or (ending in .o) an object file
+  --checked               loads and stores outside of malloc'd memory or strings stop the program
+  --sandbox <bytes>       gives the program only this much memory of its own to use
+  --allow <function>      a module or C function a sandboxed program can call, can be given more than once
+  --allow-module <module> a module a sandboxed program can INC besides std and io, can be given more than once
+  --fuel <n>              stops the program after about this many instructions
+  --time                  reports how long each phase took on stderr
+  --vars                  prints main's variables on stderr after `run`
+
+<file> can be - to read the program from stdin";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Run,
+    Check,
+    DumpAst,
+    DumpIr,
+    Build
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Jit,
+    Interp
+}
 
-mod asm;
-mod parser;
-mod modules;
+struct Options {
+    command: Command,
+    backend: Backend,
+    file: String,
+    out: Option<String>,
+    checked: bool,
+    sandbox: Option<usize>,
+    allow: Vec<String>,
+    allow_modules: Vec<String>,
+    fuel: Option<Word>,
+    time: bool,
+    vars: bool
+}
 
 fn main() {
-    let file = env::args().nth(1).unwrap();
-    let mut file = File::open(file).unwrap();
+    let opts = match options(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            exit(2);
+        }
+    };
+
+    let src = timed(opts.time, "read", || read(&opts.file)).unwrap_or_else(|e| {
+        eprintln!("error: can't read `{}`: {e}", opts.file);
+        exit(1);
+    });
+
+    let mut program = match timed(opts.time, "parse", || Program::parse(src)) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+
+    let sandbox = opts.sandbox.map(|memory| Sandbox { memory, allow: opts.allow.clone(), modules: opts.allow_modules.clone() });
+    program.set_options(rba::Options { checked: opts.checked, sandbox, fuel: opts.fuel });
+
+    if opts.command == Command::DumpAst {
+        println!("{:#?}", program.instructions());
+        return;
+    }
+
+    let diags = timed(opts.time, "check", || program.check(&BorrowingModuleProvider));
+    for d in &diags {
+        eprintln!("{d}\n");
+    }
+    if diags.iter().any(Diagnostic::is_error) {
+        exit(1);
+    }
+
+    match opts.command {
+        Command::Run => run(&program, opts.backend, opts.time, opts.vars),
+        Command::DumpIr => print!("{}", compiled(timed(opts.time, "compile", || program.ir(BorrowingModuleProvider)))),
+        Command::Build => build(&program, Path::new(opts.out.as_deref().unwrap()), opts.time),
+        Command::Check | Command::DumpAst => { }
+    }
+}
+
+fn options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options { command: Command::Run, backend: Backend::Jit, file: String::new(), out: None, checked: false, sandbox: None, allow: Vec::new(), allow_modules: Vec::new(), fuel: None,
+        time: false, vars: false };
+    let mut file = None;
+    let mut first = true;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "run" if first => { opts.command = Command::Run; }
+            "check" if first => { opts.command = Command::Check; }
+            "dump-ast" if first => { opts.command = Command::DumpAst; }
+            "dump-ir" if first => { opts.command = Command::DumpIr; }
+            "build" if first => { opts.command = Command::Build; }
+            "--backend" => {
+                opts.backend = match args.next().as_deref() {
+                    Some("jit") => Backend::Jit,
+                    Some("interp") => Backend::Interp,
+                    Some(other) => return Err(format!("unknown backend `{other}`, expected `jit` or `interp`")),
+                    None => return Err(String::from("`--backend` needs a value"))
+                };
+            }
+            "-o" => {
+                opts.out = Some(args.next().ok_or("`-o` needs a path")?);
+            }
+            "--checked" => { opts.checked = true; }
+            "--sandbox" => {
+                let bytes = args.next().ok_or("`--sandbox` needs a size")?;
+                opts.sandbox = Some(bytes.parse().map_err(|_| format!("`{bytes}` is not a size in bytes"))?);
+            }
+            "--fuel" => {
+                let n = args.next().ok_or("`--fuel` needs a number of instructions")?;
+                opts.fuel = Some(n.parse().map_err(|_| format!("`{n}` is not a number of instructions"))?);
+            }
+            "--allow" => {
+                opts.allow.push(args.next().ok_or("`--allow` needs a function")?);
+            }
+            "--allow-module" => {
+                opts.allow_modules.push(args.next().ok_or("`--allow-module` needs a module")?);
+            }
+            "--time" => { opts.time = true; }
+            "--vars" => { opts.vars = true; }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                exit(0);
+            }
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option `{arg}`")),
+            _ if file.is_none() => { file = Some(arg); }
+            _ => return Err(format!("unexpected argument `{arg}`"))
+        }
+        first = false;
+    }
+
+    opts.file = file.ok_or("no program given")?;
+
+    // `rba prog.rbasm -o prog` worked before there were commands
+    if opts.out.is_some() && opts.command == Command::Run {
+        opts.command = Command::Build;
+    }
+    if !opts.allow.is_empty() && opts.sandbox.is_none() {
+        return Err(String::from("`--allow` only means something with `--sandbox`"));
+    }
+    if !opts.allow_modules.is_empty() && opts.sandbox.is_none() {
+        return Err(String::from("`--allow-module` only means something with `--sandbox`"));
+    }
+    if opts.command == Command::Build && opts.out.is_none() {
+        return Err(String::from("`build` needs an output path, pass one with `-o`"));
+    }
+
+    Ok(opts)
+}
+
+fn read(file: &str) -> io::Result<String> {
     let mut s = String::new();
-    file.read_to_string(&mut s).unwrap();
-    let ins = asm().parse(Box::leak(s.into_boxed_str()).as_bytes()).unwrap();
+    if file == "-" {
+        io::stdin().read_to_string(&mut s)?;
+    } else {
+        s = fs::read_to_string(file)?;
+    }
 
-    dbg!(&ins);
+    Ok(s)
+}
 
-    jit(&ins);
-    // interpret(&ins);
+fn timed<T>(time: bool, phase: &str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let out = f();
+    if time {
+        eprintln!("{phase}: {:?}", start.elapsed());
+    }
+    out
 }
 
-fn jit(ins: &[Ins]) {
-    let v = asm::into_cr(ins, BorrowingModuleProvider);
-    unsafe { v() };
+// main already stopped at errors, but the backends check again for themselves
+fn compiled<T>(result: Result<T, Vec<Diagnostic>>) -> T {
+    result.unwrap_or_else(|errors| {
+        for d in errors {
+            eprintln!("{d}\n");
+        }
+        exit(1);
+    })
 }
 
-fn interpret(ins: &[Ins]) {
-    unsafe { asm::execute(ins, BorrowingModuleProvider) };
+fn run(program: &Program, backend: Backend, time: bool, vars: bool) {
+    let (result, mut state): (_, Vec<(String, Word)>) = match backend {
+        Backend::Jit => {
+            let mut engine = compiled(timed(time, "compile", || program.compile(BorrowingModuleProvider)));
+            let result = timed(time, "run", || unsafe { engine.run() });
+            (result, engine.variables().map(|(name, value)| (name.to_string(), value)).collect())
+        }
+        Backend::Interp => {
+            let mut state = HashMap::new();
+            let result = compiled(timed(time, "run", || unsafe { program.interpret(BorrowingModuleProvider, &mut state) }));
+            (result, state.into_iter().collect())
+        }
+    };
+
+    // sorted so the backends can be compared line by line
+    if vars {
+        state.sort();
+        for (name, value) in state {
+            eprintln!("{name} = {value}");
+        }
+    }
+
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+        exit(1);
+    }
 }
 
+fn build(program: &Program, out: &Path, time: bool) {
+    let object = compiled(timed(time, "compile", || program.object(BorrowingModuleProvider)));
+
+    if out.extension().is_some_and(|e| e == "o") {
+        fs::write(out, object).unwrap();
+        return;
+    }
+
+    let obj_path = out.with_extension("o");
+    fs::write(&obj_path, object).unwrap();
+    let linked = timed(time, "link", || aot::link(&obj_path, out));
+    let _ = fs::remove_file(&obj_path);
+
+    if let Err(e) = linked {
+        eprintln!("error: {e}");
+        exit(1);
+    }
+}
@@ -1,173 +1,258 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::ops::AddAssign;
-use std::os::windows::io::FromRawHandle;
-use std::process::Stdio;
-use std::ptr;
-use cranelift_jit::JITBuilder;
-use rba_derive::module;
-use crate::asm::{Addr, Word};
-
-pub trait Module<K: Into<String>, T: IntoIterator<Item=(K, *const u8)>> {
-    const NAME: &'static str;
-
-    fn symbols() -> T;
-}
-
-pub trait ModuleProvider {
-    fn add_functions(&self, builder: &mut JITBuilder, name: impl AsRef<str>);
-    fn get_ptrs(&self, hashmap: &mut HashMap<String, *const u8>, name: impl AsRef<str>);
-}
-
-pub type DefaultModuleProvider = BorrowingModuleProvider;
-pub struct BorrowingModuleProvider;
-
-impl ModuleProvider for BorrowingModuleProvider {
-    fn add_functions(&self, builder: &mut JITBuilder, name: impl AsRef<str>) {
-        match name.as_ref() {
-            "std" => {
-                for (name, addr) in Std::symbols() {
-                    builder.symbol(name, addr);
-                }
-            }
-            "io" => {
-                for (name, addr) in IO::symbols() {
-                    builder.symbol(name, addr);
-                }
-            }
-            _ => { /* unknown module */ }
-        }
-    }
-
-    fn get_ptrs(&self, hashmap: &mut HashMap<String, *const u8>, name: impl AsRef<str>) {
-        match name.as_ref() {
-            "std" => {
-                hashmap.extend(Std::symbols().map(|(a, b)| (a.to_string(), b)));
-            }
-            "io" => {
-                hashmap.extend(IO::symbols().map(|(a, b)| (a.to_string(), b)));
-            }
-            _ => { /* unknown module */ }
-        }
-    }
-}
-
-struct Std;
-
-#[module(std)]
-impl Std {
-    fn printc(val: Word) { println!("{val}") }
-    fn printa(val: Word) { print!("{}", char::from_u32(val as u32).unwrap()) }
-    fn top_8(val: Word) -> Word { val >> 56 }
-    fn addr_8(val: Addr) -> Word {
-        unsafe {
-            let ptr: *mut u8 = std::mem::transmute(val);
-            ptr.read() as Word
-        }
-    }
-}
-
-#[repr(transparent)]
-struct Wp(Word);
-
-impl Write for Wp {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let mut write: Box<dyn Write> = match self.0 {
-            0 => { Box::new(std::io::stdout()) }
-            1 => { Box::new(std::io::stderr()) }
-            2 => { panic!() }
-            n => unsafe {
-                let file: *mut File = std::mem::transmute(n);
-
-                Box::new(file.read())
-            }
-        };
-
-        let res = write.write(buf);
-        std::mem::forget(write);
-        res
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        let mut write: Box<dyn Write> = match self.0 {
-            0 => { Box::new(std::io::stdout()) }
-            1 => { Box::new(std::io::stderr()) }
-            2 => { panic!() }
-            n => unsafe {
-                let file: *mut File = std::mem::transmute(n);
-
-                Box::new(file.read())
-            }
-        };
-
-        let res = write.flush();
-        std::mem::forget(write);
-        res
-    }
-}
-
-impl Read for Wp {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let mut read: Box<dyn Read> = match self.0 {
-            0 | 1 => { panic!() }
-            2 => { Box::new(std::io::stdin()) }
-            n => unsafe {
-                let file: *mut File = std::mem::transmute(n);
-
-                Box::new(file.read())
-            }
-        };
-        let res = read.read(buf);
-        std::mem::forget(read);
-        res
-    }
-}
-
-struct IO;
-
-#[module(io)]
-impl IO {
-    fn stdout() -> Word { 0 }
-    fn stderr() -> Word { 1 }
-    fn stdin() -> Word { 2 }
-
-    fn write(mut handle: Wp, data: Addr, num: Word) {
-        unsafe {
-            let ptr: *mut u8 = std::mem::transmute(data);
-            let slice = std::slice::from_raw_parts_mut(ptr, num as usize);
-            handle.write_all(slice).unwrap();
-        }
-    }
-
-    fn read(handle: Wp, into: Addr, max: Word) -> Word {
-        unsafe {
-            let ptr: *mut u8 = std::mem::transmute(into);
-            let slice = std::slice::from_raw_parts_mut(ptr, max as usize);
-            handle.take(max).read(slice).unwrap() as Word
-        }
-    }
-
-    fn open_file(name: Addr, num: Word) -> Wp {
-        unsafe {
-            let ptr: *mut u8 = std::mem::transmute(name);
-            let slice = std::slice::from_raw_parts_mut(ptr, num as usize);
-            let string = String::from_utf8_lossy(slice);
-
-            let file = File::create(string.to_string()).unwrap();
-            let mut bx = Box::new(file);
-
-            let ptr = (bx.as_mut() as *mut File) as usize;
-            std::mem::forget(bx);
-            Wp(ptr as Word)
-        }
-    }
-
-    fn close_file(file: Wp) {
-        unsafe {
-            let file: *mut File = std::mem::transmute(file);
-
-            ptr::drop_in_place(file);
-        }
-    }
-}
\ No newline at end of file
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::slice;
+use cranelift_jit::JITBuilder;
+use rba_runtime::{Std, IO, Word};
+use crate::dynamic;
+pub use rba_runtime::{FnSig, Kind, Module};
+
+pub trait ModuleProvider {
+    fn add_functions(&self, builder: &mut JITBuilder, name: impl AsRef<str>);
+    fn get_ptrs(&self, hashmap: &mut HashMap<String, *const u8>, name: impl AsRef<str>);
+    fn modules(&self) -> Vec<String>;
+    fn signatures(&self, name: impl AsRef<str>) -> Option<Vec<(String, FnSig)>>;
+
+    // closures that make up (part of) module `name`, these have no symbol of their own
+    // so the backends call them through `rba_host_call` instead
+    fn host_functions(&self, _name: impl AsRef<str>) -> Vec<(String, HostFn)> {
+        Vec::new()
+    }
+
+    // why module `name` can't be included, when there's more to say than that it doesn't exist
+    fn load_error(&self, _name: impl AsRef<str>) -> Option<String> {
+        None
+    }
+}
+
+// so a provider can be lent to a program instead of given away
+impl<P: ModuleProvider> ModuleProvider for &P {
+    fn add_functions(&self, builder: &mut JITBuilder, name: impl AsRef<str>) {
+        (**self).add_functions(builder, name);
+    }
+
+    fn get_ptrs(&self, hashmap: &mut HashMap<String, *const u8>, name: impl AsRef<str>) {
+        (**self).get_ptrs(hashmap, name);
+    }
+
+    fn modules(&self) -> Vec<String> {
+        (**self).modules()
+    }
+
+    fn signatures(&self, name: impl AsRef<str>) -> Option<Vec<(String, FnSig)>> {
+        (**self).signatures(name)
+    }
+
+    fn host_functions(&self, name: impl AsRef<str>) -> Vec<(String, HostFn)> {
+        (**self).host_functions(name)
+    }
+
+    fn load_error(&self, name: impl AsRef<str>) -> Option<String> {
+        (**self).load_error(name)
+    }
+}
+
+// a rust closure callable from CALL, it gets the arguments as a slice and 0 is returned if it has nothing to give
+#[derive(Clone)]
+pub struct HostFn {
+    pub sig: FnSig,
+    f: Rc<HostClosure>
+}
+
+type HostClosure = dyn Fn(&[Word]) -> Word;
+
+impl HostFn {
+    pub fn new<A, R>(f: impl IntoHostFn<A, R>) -> HostFn {
+        f.into_host_fn()
+    }
+
+    pub fn call(&self, args: &[Word]) -> Word {
+        (self.f)(args)
+    }
+}
+
+// what a jit trampoline calls, `f` points to a HostFn that outlives the compiled code
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn rba_host_call(f: *const HostFn, args: *const Word, len: usize) -> Word {
+    (*f).call(slice::from_raw_parts(args, len))
+}
+
+// closures taking up to 6 Words and returning either a Word or nothing
+pub trait IntoHostFn<A, R> {
+    fn into_host_fn(self) -> HostFn;
+}
+
+pub trait HostReturn {
+    const RETURNS: bool;
+    fn into_word(self) -> Word;
+}
+
+impl HostReturn for Word {
+    const RETURNS: bool = true;
+    fn into_word(self) -> Word { self }
+}
+
+impl HostReturn for () {
+    const RETURNS: bool = false;
+    fn into_word(self) -> Word { 0 }
+}
+
+macro_rules! into_host_fn {
+    ($len:literal $(, $a:ident: $t:ty)*) => {
+        impl<F: Fn($($t),*) -> R + 'static, R: HostReturn> IntoHostFn<($($t,)*), R> for F {
+            fn into_host_fn(self) -> HostFn {
+                HostFn {
+                    sig: FnSig::new($len, R::RETURNS),
+                    #[allow(unused_variables, unused_mut)]
+                    f: Rc::new(move |args: &[Word]| {
+                        let mut args = args.iter().copied();
+                        $(let $a = args.next().unwrap_or_default();)*
+                        self($($a),*).into_word()
+                    })
+                }
+            }
+        }
+    };
+}
+
+into_host_fn!(0);
+into_host_fn!(1, a: Word);
+into_host_fn!(2, a: Word, b: Word);
+into_host_fn!(3, a: Word, b: Word, c: Word);
+into_host_fn!(4, a: Word, b: Word, c: Word, d: Word);
+into_host_fn!(5, a: Word, b: Word, c: Word, d: Word, e: Word);
+into_host_fn!(6, a: Word, b: Word, c: Word, d: Word, e: Word, f: Word);
+
+pub type DefaultModuleProvider = BorrowingModuleProvider;
+pub struct BorrowingModuleProvider;
+
+impl ModuleProvider for BorrowingModuleProvider {
+    fn add_functions(&self, builder: &mut JITBuilder, name: impl AsRef<str>) {
+        match name.as_ref() {
+            "std" => {
+                for (name, addr) in Std::symbols() {
+                    builder.symbol(name, addr);
+                }
+            }
+            "io" => {
+                for (name, addr) in IO::symbols() {
+                    builder.symbol(name, addr);
+                }
+            }
+            name => {
+                if let Ok(module) = dynamic::load(name) {
+                    for (name, addr) in module.symbols() {
+                        builder.symbol(name, addr);
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_ptrs(&self, hashmap: &mut HashMap<String, *const u8>, name: impl AsRef<str>) {
+        match name.as_ref() {
+            "std" => {
+                hashmap.extend(Std::symbols().map(|(a, b)| (a.to_string(), b)));
+            }
+            "io" => {
+                hashmap.extend(IO::symbols().map(|(a, b)| (a.to_string(), b)));
+            }
+            name => {
+                if let Ok(module) = dynamic::load(name) {
+                    hashmap.extend(module.symbols().map(|(a, b)| (a.to_string(), b)));
+                }
+            }
+        }
+    }
+
+    fn modules(&self) -> Vec<String> {
+        vec![Std::NAME.to_string(), IO::NAME.to_string()]
+    }
+
+    fn signatures(&self, name: impl AsRef<str>) -> Option<Vec<(String, FnSig)>> {
+        let sigs = match name.as_ref() {
+            "std" => Std::SIGNATURES,
+            "io" => IO::SIGNATURES,
+            name => return dynamic::load(name).ok().map(|module| module.signatures())
+        };
+
+        Some(sigs.iter().map(|(a, b)| (a.to_string(), *b)).collect())
+    }
+
+    fn load_error(&self, name: impl AsRef<str>) -> Option<String> {
+        match name.as_ref() {
+            "std" | "io" => None,
+            name => dynamic::load(name).err()
+        }
+    }
+}
+
+// another provider plus the `host` module, which is whatever closures get registered with it
+pub struct Host<P: ModuleProvider = BorrowingModuleProvider> {
+    inner: P,
+    functions: Vec<(String, HostFn)>
+}
+
+impl Host {
+    pub fn new() -> Host {
+        Host::with(BorrowingModuleProvider)
+    }
+}
+
+impl Default for Host {
+    fn default() -> Host {
+        Host::new()
+    }
+}
+
+impl<P: ModuleProvider> Host<P> {
+    pub const NAME: &'static str = "host";
+
+    pub fn with(inner: P) -> Host<P> {
+        Host { inner, functions: Vec::new() }
+    }
+
+    // registering a name twice replaces the first one
+    pub fn register<A, R>(&mut self, name: impl Into<String>, f: impl IntoHostFn<A, R>) -> &mut Host<P> {
+        let name = name.into();
+        self.functions.retain(|(n, _)| *n != name);
+        self.functions.push((name, HostFn::new(f)));
+        self
+    }
+}
+
+impl<P: ModuleProvider> ModuleProvider for Host<P> {
+    fn add_functions(&self, builder: &mut JITBuilder, name: impl AsRef<str>) {
+        self.inner.add_functions(builder, name);
+    }
+
+    fn get_ptrs(&self, hashmap: &mut HashMap<String, *const u8>, name: impl AsRef<str>) {
+        self.inner.get_ptrs(hashmap, name);
+    }
+
+    fn modules(&self) -> Vec<String> {
+        let mut modules = self.inner.modules();
+        modules.push(Self::NAME.to_string());
+        modules
+    }
+
+    fn signatures(&self, name: impl AsRef<str>) -> Option<Vec<(String, FnSig)>> {
+        if name.as_ref() == Self::NAME {
+            return Some(self.functions.iter().map(|(n, f)| (n.clone(), f.sig)).collect());
+        }
+        self.inner.signatures(name)
+    }
+
+    fn host_functions(&self, name: impl AsRef<str>) -> Vec<(String, HostFn)> {
+        if name.as_ref() == Self::NAME {
+            return self.functions.clone();
+        }
+        self.inner.host_functions(name)
+    }
+
+    fn load_error(&self, name: impl AsRef<str>) -> Option<String> {
+        if name.as_ref() == Self::NAME {
+            return None;
+        }
+        self.inner.load_error(name)
+    }
+}
@@ -1,90 +1,306 @@
-use pom::parser::*;
-use pom::Parser;
-
-use std::collections::HashMap;
-use std::str::{self, FromStr};
-use crate::asm::{AsmIns, Const, Val, Var};
-
-fn space() -> Parser<u8, ()> {
-    one_of(b" \t\r\n").repeat(0..).discard()
-}
-
-fn number() -> Parser<u8, u64> {
-    let integer = one_of(b"123456789") - one_of(b"_0123456789").repeat(0..) | sym(b'0');
-    integer.collect().convert(str::from_utf8).convert(|s| u64::from_str(&s.replace("_", "")))
-}
-
-fn val() -> Parser<u8, Val> {
-    var().map(Val::Var) | number().map(|w| Val::Const(Const::Word(w))) | string().map(|x| {
-        let ret = Val::Const(Const::Str(x.as_ptr()));
-        std::mem::forget(x);
-        ret
-    })
-}
-
-fn string() -> Parser<u8, String> {
-    let special_char = sym(b'\\') | sym(b'/') | sym(b'"')
-        | sym(b'b').map(|_|b'\x08') | sym(b'f').map(|_|b'\x0C')
-        | sym(b'n').map(|_|b'\n') | sym(b'r').map(|_|b'\r') | sym(b't').map(|_|b'\t');
-    let escape_sequence = sym(b'\\') * special_char;
-    let string = sym(b'"') * (none_of(b"\\\"") | escape_sequence).repeat(0..) - sym(b'"');
-    string.convert(String::from_utf8)
-}
-
-fn var() -> Parser<u8, Var> {
-    space() * none_of(b" \";&0123456789,").repeat(1..).collect().convert(str::from_utf8).map(String::from).map(Var::Named)
-    | sym(b'&') * call(val).map(|x| Var::Addr(Box::new(x)))
-}
-
-fn mov() -> Parser<u8, (Val, Var)> {
-    space() * val() + space() * var()
-}
-
-fn op() -> Parser<u8, (Var, Val)> {
-    space() * var() + space() * val()
-}
-
-fn swap() -> Parser<u8, (Var, Var)> {
-    space() * var() + space() * var()
-}
-
-fn label() -> Parser<u8, String> {
-    space() * none_of(b" ;\"").repeat(0..).collect().convert(str::from_utf8).map(String::from)
-}
-
-fn jmp() -> Parser<u8, (Val, String)> {
-    space() * val() + space() * label()
-}
-
-pub fn fcall() -> Parser<u8, (String, Vec<Val>, Option<Var>)> {
-    (label() + space() * list(val(), sym(b',') * space()) - space() + var().opt()).map(|((a, b), c)| (a, b, c))
-}
-
-pub fn rcall() -> Parser<u8, (String, Option<Var>)> {
-    label() + space() * space() * var().opt()
-}
-
-
-fn ins() -> Parser<u8, AsmIns> {
-    space() * (
-            (seq(b"INCLUDE") | seq(b"INC")) * space() * label().map(AsmIns::Include)
-        |   seq(b"MOV") * mov().map(|(a, b)| AsmIns::Move(a, b))
-        |   seq(b"SWAP") * swap().map(|(a, b)| AsmIns::Swap(a, b))
-        |   seq(b"ADD") * op().map(|(a, b)| AsmIns::Add(a, b))
-        |   seq(b"SUB") * op().map(|(a, b)| AsmIns::Sub(a, b))
-        |   seq(b"MUL") * op().map(|(a, b)| AsmIns::Mul(a, b))
-        |   seq(b"DIV") * op().map(|(a, b)| AsmIns::Div(a, b))
-        |   seq(b"MOD") * op().map(|(a, b)| AsmIns::Mod(a, b))
-        |   seq(b"LABEL:") * space() * label().map(AsmIns::Label)
-        |   seq(b"JZ") * jmp().map(|(a, b)| AsmIns::JZ(a, b))
-        |   seq(b"JNZ") * jmp().map(|(a, b)| AsmIns::JNz(a, b))
-        |   seq(b"CALL") * fcall().map(|(a, b, c)| AsmIns::Call(a, b, c))
-        |   seq(b"RCALL") * rcall().map(|(a, b)| AsmIns::Call(a, Vec::new(), b))
-        |   seq(b"OUT") * space() * val().map(AsmIns::Output)
-        |   seq(b"NOP").map(|_| AsmIns::Nop)
-    ) - space()
-}
-
-pub fn asm() -> Parser<u8, Vec<AsmIns>> {
-    list(ins(), sym(b';'))
-}
\ No newline at end of file
+// pom's combinators are operators, `|` binding loosest is the grammar working as intended
+#![allow(clippy::precedence)]
+
+use pom::parser::*;
+
+use std::ops::Range;
+use std::str::{self, FromStr};
+use crate::asm::{AsmIns, Cmp, Const, Val, Var, Width, ERRNO};
+use crate::diagnostic::Diagnostic;
+
+const MNEMONICS: &[&str] = &[
+    "INC", "INCLUDE", "MOV", "SWAP", "ADD", "SUB", "MUL", "DIV", "MOD", "SDIV", "SMOD", "NEG",
+    "AND", "OR", "XOR", "NOT", "SHL", "SHR", "SAR", "ROL", "ROR", "POPCNT", "CLZ", "CTZ",
+    "LABEL:", "JZ", "JNZ", "JMP", "JEQ", "JNE", "JLT", "JLE", "JGT", "JGE",
+    "JSLT", "JSLE", "JSGT", "JSGE", "JERR", "CALL", "RCALL", "OUT", "NOP", "FN", "RET", "END"
+];
+
+// comments count as whitespace, so they can go anywhere a space can
+fn space<'a>() -> Parser<'a, u8, ()> {
+    (one_of(b" \t\r\n").discard() | comment()).repeat(0..).discard()
+}
+
+fn comment<'a>() -> Parser<'a, u8, ()> {
+    let line = (seq(b"//") | seq(b"#")) * none_of(b"\n").repeat(0..);
+    let block = seq(b"/*") * (!seq(b"*/") * any()).repeat(0..) - seq(b"*/");
+    line.discard() | block.discard()
+}
+
+// a mnemonic ends where its word does, so `MOVE` is an unknown instruction and not `MOV` with an `E`
+fn mnemonic<'a>(name: &'a [u8]) -> Parser<'a, u8, ()> {
+    seq(name).discard() - !(!comment() * none_of(b" \t\r\n;"))
+}
+
+// negative numbers are stored as their two's complement
+fn number<'a>() -> Parser<'a, u8, u64> {
+    let integer = one_of(b"123456789") - one_of(b"_0123456789").repeat(0..) | sym(b'0');
+    let magnitude = integer.collect().convert(str::from_utf8).convert(|s| u64::from_str(&s.replace("_", "")));
+
+    (sym(b'-').opt() + magnitude).convert(|(neg, m)| match neg {
+        None => Ok(m),
+        Some(_) if m <= i64::MIN.unsigned_abs() => Ok(m.wrapping_neg()),
+        Some(_) => Err("negative number does not fit in 64 bits")
+    })
+}
+
+fn val<'a>() -> Parser<'a, u8, Val> {
+    number().map(|w| Val::Const(Const::Word(w))) | var().map(Val::Var) | string().map(|x| Val::Const(Const::Str(x.into())))
+}
+
+fn string<'a>() -> Parser<'a, u8, String> {
+    let special_char = sym(b'\\') | sym(b'/') | sym(b'"')
+        | sym(b'b').map(|_|b'\x08') | sym(b'f').map(|_|b'\x0C')
+        | sym(b'n').map(|_|b'\n') | sym(b'r').map(|_|b'\r') | sym(b't').map(|_|b'\t');
+    let escape_sequence = sym(b'\\') * special_char;
+    let string = sym(b'"') * (none_of(b"\\\"") | escape_sequence).repeat(0..) - sym(b'"');
+    string.convert(String::from_utf8)
+}
+
+fn name<'a>() -> Parser<'a, u8, String> {
+    space() * none_of(b" \t\r\n\";&0123456789,#/").repeat(1..).collect().convert(str::from_utf8).map(String::from)
+}
+
+// B& H& W& and D& (or just &) read/write 8, 16, 32 and 64 bits at an address
+fn var<'a>() -> Parser<'a, u8, Var> {
+    let width = sym(b'B').map(|_| Width::W8) | sym(b'H').map(|_| Width::W16)
+        | sym(b'W').map(|_| Width::W32) | sym(b'D').map(|_| Width::W64);
+
+    space() * (width - sym(b'&') + call(val)).map(|(w, x)| Var::Addr(Box::new(x), w))
+    | name().map(Var::Named)
+    | space() * sym(b'&') * call(val).map(|x| Var::Addr(Box::new(x), Width::W64))
+}
+
+fn mov<'a>() -> Parser<'a, u8, (Val, Var)> {
+    space() * val() + space() * var()
+}
+
+fn op<'a>() -> Parser<'a, u8, (Var, Val)> {
+    space() * var() + space() * val()
+}
+
+fn swap<'a>() -> Parser<'a, u8, (Var, Var)> {
+    space() * var() + space() * var()
+}
+
+// a comment right after a label isn't part of it
+fn label<'a>() -> Parser<'a, u8, String> {
+    let comment = seq(b"//") | seq(b"#") | seq(b"/*");
+    space() * (!comment * none_of(b" \t\r\n;\"")).repeat(0..).collect().convert(str::from_utf8).map(String::from)
+}
+
+fn jmp<'a>() -> Parser<'a, u8, (Val, String)> {
+    space() * val() + space() * label()
+}
+
+fn jcmp<'a>() -> Parser<'a, u8, (Val, Val, String)> {
+    (space() * val() - space() - sym(b',') + space() * val() - space() - sym(b',') + space() * label())
+        .map(|((a, b), c)| (a, b, c))
+}
+
+pub fn fcall<'a>() -> Parser<'a, u8, (String, Vec<Val>, Option<Var>)> {
+    (label() + space() * list(val(), space() * sym(b',') * space()) - space() + var().opt()).map(|((a, b), c)| (a, b, c))
+}
+
+fn func<'a>() -> Parser<'a, u8, (String, Vec<String>)> {
+    label() + space() * list(name(), space() * sym(b',') * space())
+}
+
+pub fn rcall<'a>() -> Parser<'a, u8, (String, Option<Var>)> {
+    label() + space() * space() * var().opt()
+}
+
+
+fn ins<'a>() -> Parser<'a, u8, AsmIns> {
+    space() * (
+            (mnemonic(b"INCLUDE") | mnemonic(b"INC")) * (space() * (string() | label())).expect("<MODULE>").map(AsmIns::Include)
+        |   mnemonic(b"MOV") * mov().expect("<VAL> <VAR>").map(|(a, b)| AsmIns::Move(a, b))
+        |   mnemonic(b"SWAP") * swap().expect("<VAR> <VAR>").map(|(a, b)| AsmIns::Swap(a, b))
+        |   mnemonic(b"ADD") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Add(a, b))
+        |   mnemonic(b"SUB") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Sub(a, b))
+        |   mnemonic(b"MUL") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Mul(a, b))
+        |   mnemonic(b"DIV") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Div(a, b))
+        |   mnemonic(b"MOD") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Mod(a, b))
+        |   mnemonic(b"SDIV") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::SDiv(a, b))
+        |   mnemonic(b"SMOD") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::SMod(a, b))
+        |   mnemonic(b"NEG") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Neg(a, b))
+        |   mnemonic(b"AND") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::And(a, b))
+        |   mnemonic(b"OR") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Or(a, b))
+        |   mnemonic(b"XOR") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Xor(a, b))
+        |   mnemonic(b"NOT") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Not(a, b))
+        |   mnemonic(b"SHL") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Shl(a, b))
+        |   mnemonic(b"SHR") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Shr(a, b))
+        |   mnemonic(b"SAR") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Sar(a, b))
+        |   mnemonic(b"ROL") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Rol(a, b))
+        |   mnemonic(b"ROR") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Ror(a, b))
+        |   mnemonic(b"POPCNT") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Popcnt(a, b))
+        |   mnemonic(b"CLZ") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Clz(a, b))
+        |   mnemonic(b"CTZ") * op().expect("<VAR> <VAL>").map(|(a, b)| AsmIns::Ctz(a, b))
+        |   seq(b"LABEL:") * (space() * label()).expect("<LABEL>").map(AsmIns::Label)
+        |   mnemonic(b"JZ") * jmp().expect("<VAL> <LABEL>").map(|(a, b)| AsmIns::JZ(a, b))
+        |   mnemonic(b"JNZ") * jmp().expect("<VAL> <LABEL>").map(|(a, b)| AsmIns::JNz(a, b))
+        |   mnemonic(b"JMP") * (space() * label()).expect("<LABEL>").map(AsmIns::Jmp)
+        |   mnemonic(b"JERR") * (space() * label()).expect("<LABEL>").map(AsmIns::JErr)
+        |   jcmp_op()
+        |   mnemonic(b"CALL") * fcall().expect("<LABEL> <VAL>, ... <VAR?>").map(call_ins)
+        |   mnemonic(b"RCALL") * rcall().expect("<LABEL> <VAR?>").map(|(a, b)| AsmIns::Call(a, Vec::new(), b))
+        |   mnemonic(b"OUT") * (space() * val()).expect("<VAL>").map(AsmIns::Output)
+        |   mnemonic(b"NOP").map(|_| AsmIns::Nop)
+        |   mnemonic(b"FN") * func().expect("<LABEL> <VAR>, ...").map(|(a, b)| AsmIns::Function(a, b, Vec::new()))
+        |   mnemonic(b"RET") * (space() * val()).opt().map(AsmIns::Return)
+    )
+}
+
+// errno takes nothing, so in `CALL errno e` the variable is where the code goes
+fn call_ins((name, mut params, out): (String, Vec<Val>, Option<Var>)) -> AsmIns {
+    match params.as_slice() {
+        [Val::Var(_)] if name == ERRNO && out.is_none() => {
+            let Some(Val::Var(out)) = params.pop() else { unreachable!() };
+            AsmIns::Call(name, params, Some(out))
+        }
+        _ => AsmIns::Call(name, params, out)
+    }
+}
+
+fn jcmp_op<'a>() -> Parser<'a, u8, AsmIns> {
+    let ops = [
+        (&b"JEQ"[..], Cmp::Eq), (b"JNE", Cmp::Ne),
+        (b"JLT", Cmp::Lt), (b"JLE", Cmp::Le), (b"JGT", Cmp::Gt), (b"JGE", Cmp::Ge),
+        (b"JSLT", Cmp::SLt), (b"JSLE", Cmp::SLe), (b"JSGT", Cmp::SGt), (b"JSGE", Cmp::SGe)
+    ];
+
+    ops.into_iter()
+        .map(|(name, cmp)| mnemonic(name) * jcmp().expect("<VAL>, <VAL>, <LABEL>").map(move |(a, b, l)| AsmIns::JCmp(cmp, a, b, l)))
+        .reduce(|a, b| a | b)
+        .unwrap()
+}
+
+// every instruction along with the byte range it came from
+type Spanned = (Vec<AsmIns>, Vec<Range<usize>>);
+
+// parses a whole program, instructions are separated by `;`, the last one may leave it off,
+// along with the byte range each instruction came from, a function's header comes right
+// before the instructions in its body
+pub fn asm_spanned(src: &[u8]) -> Result<Spanned, Box<Diagnostic>> {
+    let ins = ins();
+    let end = mnemonic(b"END");
+    let space = space();
+
+    let mut out = Vec::new();
+    let mut spans = Vec::new();
+    let mut pos = skip(&space, src, 0)?;
+
+    // the FN still waiting for its END, functions can't be nested so there is at most one
+    let mut open: Option<(usize, AsmIns)> = None;
+
+    while pos < src.len() {
+        let start = pos;
+
+        // `;;` is an empty statement, there's nothing to parse
+        if src[start] == b';' {
+            pos = skip(&space, src, start + 1)?;
+            continue;
+        }
+
+        if let Ok((_, next)) = end.parse_at(src, start) {
+            match open.take() {
+                Some((_, func)) => { out.push(func); }
+                None => {
+                    return Err(Box::new(Diagnostic::new(src, start, 3, "`END` without a matching `FN`")
+                        .instruction(statement(src, start))));
+                }
+            }
+            pos = skip(&space, src, next)?;
+        } else {
+            match ins.parse_at(src, start) {
+                Ok((i @ AsmIns::Function(..), next)) => {
+                    if open.is_some() {
+                        return Err(Box::new(Diagnostic::new(src, start, 2, "functions can't be nested")
+                            .expected(["END"])
+                            .instruction(statement(src, start))));
+                    }
+                    spans.push(start..next);
+                    open = Some((start, i));
+                    pos = skip(&space, src, next)?;
+                }
+                Ok((i, next)) => {
+                    match &mut open {
+                        Some((_, AsmIns::Function(_, _, body))) => { body.push(i); }
+                        _ => { out.push(i); }
+                    }
+                    spans.push(start..next);
+                    pos = skip(&space, src, next)?;
+                }
+                Err(pom::Error::Expect { message, inner, .. }) => {
+                    let at = error_pos(&inner).unwrap_or(src.len());
+                    let operands = message.trim_start_matches("Expect ");
+
+                    return Err(Box::new(Diagnostic::new(src, at, token(src, at), format!("invalid operands for `{}`", word(src, start)))
+                        .expected([operands])
+                        .instruction(statement(src, start))));
+                }
+                Err(_) => {
+                    let found = word(src, start);
+                    return Err(Box::new(Diagnostic::new(src, start, found.len(), format!("unknown instruction `{found}`"))
+                        .expected(MNEMONICS.iter().copied())
+                        .instruction(statement(src, start))));
+                }
+            }
+        }
+
+        if pos >= src.len() { break; }
+        if src[pos] != b';' {
+            return Err(Box::new(Diagnostic::new(src, pos, token(src, pos), "expected `;` after instruction")
+                .expected([";"])
+                .instruction(statement(src, start))));
+        }
+        pos = skip(&space, src, pos + 1)?;
+    }
+
+    if let Some((start, _)) = open {
+        return Err(Box::new(Diagnostic::new(src, start, 2, "function is missing its `END`")
+            .expected(["END"])
+            .instruction(statement(src, start))));
+    }
+
+    Ok((out, spans))
+}
+
+// whitespace never fails to parse, a block comment that runs off the end of the file just stops it early
+fn skip<'a>(space: &Parser<'a, u8, ()>, src: &'a [u8], pos: usize) -> Result<usize, Box<Diagnostic>> {
+    let pos = space.parse_at(src, pos).map_or(pos, |(_, p)| p);
+
+    if src[pos..].starts_with(b"/*") {
+        Err(Box::new(Diagnostic::new(src, pos, 2, "unterminated block comment").expected(["*/"])))
+    } else {
+        Ok(pos)
+    }
+}
+
+// the deepest position an error points to, running out of input has none
+fn error_pos(err: &pom::Error) -> Option<usize> {
+    match err {
+        pom::Error::Incomplete => None,
+        pom::Error::Mismatch { position, .. } | pom::Error::Conversion { position, .. } => Some(*position),
+        pom::Error::Expect { inner, position, .. } => error_pos(inner).or(Some(*position)),
+        pom::Error::Custom { inner, position, .. } => inner.as_deref().and_then(error_pos).or(Some(*position)),
+    }
+}
+
+// up to a comment too, so `FOO// x` is about `FOO`
+fn word(src: &[u8], pos: usize) -> String {
+    let rest = &src[pos..];
+    let len = (0..rest.len())
+        .position(|i| b" \t\r\n;#".contains(&rest[i]) || rest[i..].starts_with(b"//") || rest[i..].starts_with(b"/*"))
+        .unwrap_or(rest.len());
+    String::from_utf8_lossy(&src[pos..pos + len]).to_string()
+}
+
+fn token(src: &[u8], pos: usize) -> usize {
+    src.get(pos..).map_or(0, |rest| rest.iter().position(|c| b" \t\r\n;,".contains(c)).unwrap_or(rest.len()))
+}
+
+pub(crate) fn statement(src: &[u8], pos: usize) -> String {
+    let len = src[pos..].iter().position(|&c| c == b';').unwrap_or(src.len() - pos);
+    let text = String::from_utf8_lossy(&src[pos..pos + len]);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
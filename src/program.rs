@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::aot;
+use crate::asm::{self, AsmIns, Word, ERRNO};
+use crate::bytecode::Bytecode;
+use crate::check;
+use crate::diagnostic::Diagnostic;
+use crate::engine::Engine;
+use crate::modules::ModuleProvider;
+use crate::parser::asm_spanned;
+use rba_runtime::Error;
+
+// a parsed program, compiling it stops at errors check would report, warnings are up to the caller
+pub struct Program {
+    src: String,
+    ins: Vec<AsmIns>,
+    spans: Vec<Range<usize>>,
+    options: Options
+}
+
+// how a program gets compiled, the same for the jit, aot and the interpreter
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    // every load and store has to be inside something malloc gave out or a string, anything else traps
+    pub checked: bool,
+    pub sandbox: Option<Sandbox>,
+    // how many instructions a run can go on for before it's stopped (labels don't count), the jit
+    // only looks before jumps, calls, OUT and input so it can change a few variables past it
+    pub fuel: Option<Word>
+}
+
+// a program that can only get at memory of its own, addresses are offsets into `memory` bytes
+// which hold its strings and whatever malloc gives out. Besides its own FNs and host closures
+// it can only call malloc, free, printc and what's in `allow`, module functions get their
+// addresses as real pointers once the whole range (the Len after the Addr, or else a byte) is
+// inside the memory, C functions get them as they are.
+// It can only INC the provider's own modules and what's in `modules`, shared libraries run
+// code as soon as they're loaded
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    pub memory: usize,
+    pub allow: Vec<String>,
+    pub modules: Vec<String>
+}
+
+impl Sandbox {
+    pub fn new(memory: usize) -> Sandbox {
+        Sandbox { memory, allow: Vec::new(), modules: Vec::new() }
+    }
+
+    pub fn allow(mut self, name: impl Into<String>) -> Sandbox {
+        self.allow.push(name.into());
+        self
+    }
+
+    pub fn allow_module(mut self, module: impl Into<String>) -> Sandbox {
+        self.modules.push(module.into());
+        self
+    }
+
+    // whether a CALL of `name` traps instead of going ahead, `own` is whether it's one of the
+    // program's FNs or host closures, which are there to call without being allowed. Module and
+    // C functions are denied unless they're in `allow` (or are malloc, free and printc)
+    pub(crate) fn denies(&self, name: &str, own: bool) -> bool {
+        !own && ![ERRNO, "printc", "malloc", "free"].contains(&name) && !self.allow.iter().any(|a| a == name)
+    }
+
+    // whether an INC can go ahead, nothing gets loaded to find out
+    pub(crate) fn includes(&self, module: &str, provider: &impl ModuleProvider) -> bool {
+        provider.modules().iter().any(|m| m == module) || self.modules.iter().any(|m| m == module)
+    }
+}
+
+impl Options {
+    // malloc and free swapped for the runtime's (the name programs call, its symbol and where it is),
+    // a sandbox's hand out its own memory and a checked program's keep track of what they gave out
+    pub(crate) fn allocator(&self) -> Vec<(&'static str, &'static str, *const u8)> {
+        if self.sandbox.is_some() {
+            vec![("malloc", "rba_sandbox_malloc", rba_runtime::rba_sandbox_malloc as *const u8),
+                 ("free", "rba_sandbox_free", rba_runtime::rba_sandbox_free as *const u8)]
+        } else if self.checked {
+            vec![("malloc", "rba_malloc", rba_runtime::rba_malloc as *const u8),
+                 ("free", "rba_free", rba_runtime::rba_free as *const u8)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Program {
+    pub fn parse(src: impl Into<String>) -> Result<Program, Box<Diagnostic>> {
+        let src = src.into();
+        let (ins, spans) = asm_spanned(src.as_bytes())?;
+
+        Ok(Program { src, ins, spans, options: Options::default() })
+    }
+
+    pub fn instructions(&self) -> &[AsmIns] {
+        &self.ins
+    }
+
+    pub fn source(&self) -> &[u8] {
+        self.src.as_bytes()
+    }
+
+    pub fn set_options(&mut self, options: Options) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    // the line the instruction an error happened at is on, counting function bodies as they are in the source
+    pub fn line(&self, at: usize) -> Option<usize> {
+        let start = self.spans.get(at)?.start;
+        Some(self.src.as_bytes()[..start].iter().filter(|&&c| c == b'\n').count() + 1)
+    }
+
+    // fills in the line of an error the program raised
+    pub(crate) fn locate(&self, error: Error) -> Error {
+        let line = error.at.and_then(|at| self.line(at));
+        Error { line, ..error }
+    }
+
+    // sorted by where they are in the source
+    pub fn check(&self, provider: &impl ModuleProvider) -> Vec<Diagnostic> {
+        check::check(&self.ins, &self.spans, self.src.as_bytes(), provider, self.options.sandbox.as_ref())
+    }
+
+    // the backends take the program as it is, so anything check calls an error stops it here
+    fn errors(&self, provider: &impl ModuleProvider) -> Result<(), Vec<Diagnostic>> {
+        let errors: Vec<_> = self.check(provider).into_iter().filter(Diagnostic::is_error).collect();
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    pub fn compile(&self, provider: impl ModuleProvider) -> Result<Engine, Vec<Diagnostic>> {
+        self.errors(&provider)?;
+        Ok(Engine::new(self, provider))
+    }
+
+    // a relocatable object with a C main, see aot::link
+    pub fn object(&self, provider: impl ModuleProvider) -> Result<Vec<u8>, Vec<Diagnostic>> {
+        self.errors(&provider)?;
+        let linked = check::check_object(&self.ins, &self.spans, self.src.as_bytes());
+        if !linked.is_empty() {
+            return Err(linked);
+        }
+        Ok(aot::into_object(&self.ins, provider, &self.options))
+    }
+
+    // the cranelift ir of main and every FN, for a program that would compile
+    pub fn ir(&self, provider: impl ModuleProvider) -> Result<String, Vec<Diagnostic>> {
+        self.errors(&provider)?;
+        Ok(asm::into_ir(&self.ins))
+    }
+
+    // compiled for the interpreter, worth keeping around to run the program more than once
+    pub fn bytecode(&self, provider: impl ModuleProvider) -> Result<Bytecode, Vec<Diagnostic>> {
+        self.errors(&provider)?;
+        Ok(Bytecode::compile(&self.ins, provider, &self.options))
+    }
+
+    // main's variables start out as whatever is in `vars` and are left there afterwards,
+    // the inner error is one the program raised and didn't catch, it runs with the current
+    // streams, `bytecode` has setters for its own,
+    // unsafe because the program can read and write any address
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn interpret(&self, provider: impl ModuleProvider, vars: &mut HashMap<String, Word>) -> Result<Result<(), Error>, Vec<Diagnostic>> {
+        Ok(self.bytecode(provider)?.run(vars).map_err(|e| self.locate(e)))
+    }
+}
@@ -0,0 +1,66 @@
+// programs compiled ahead of time into an object, linked with the runtime and run as their own executable
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use rba::modules::BorrowingModuleProvider;
+use rba::Program;
+
+fn tmp(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+// `rba build` next to the runtime library cargo left in target
+fn build(src: &str, name: &str) -> PathBuf {
+    let file = tmp(&format!("{name}.rbasm"));
+    fs::write(&file, src).unwrap();
+    let out = tmp(name);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_rba")).arg("build").arg(&file).arg("-o").arg(&out).status().unwrap();
+    assert!(status.success());
+    out
+}
+
+fn run(exe: &Path, stdin: &str) -> Output {
+    let mut child = Command::new(exe).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn object() {
+    let program = Program::parse("MOV 1 a; OUT a;").unwrap();
+    let object = program.object(BorrowingModuleProvider).unwrap();
+    if cfg!(target_os = "linux") {
+        assert_eq!(&object[..4], b"\x7fELF");
+    }
+}
+
+#[test]
+fn executable() {
+    let exe = build(&fs::read_to_string("examples/fib.rbasm").unwrap(), "aot_fib");
+    let out = run(&exe, "10\n");
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "55\n");
+}
+
+#[test]
+fn uncaught_error() {
+    let exe = build("INC io;\nMOV 1 a;\nCALL close_file 12345;\nOUT 2;", "aot_error");
+    let out = run(&exe, "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "error: io::close_file: 12345 is not an open file at instruction 2\n");
+}
+
+#[test]
+fn object_only() {
+    // `-o` ending in .o stops before linking
+    let file = tmp("aot_object.rbasm");
+    fs::write(&file, "OUT 1;").unwrap();
+    let out = tmp("aot_object.o");
+    let status = Command::new(env!("CARGO_BIN_EXE_rba")).arg("build").arg(&file).arg("-o").arg(&out).status().unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read(&out).unwrap(), Program::parse("OUT 1;").unwrap().object(BorrowingModuleProvider).unwrap());
+}
@@ -0,0 +1,67 @@
+// FN calls go MAX_DEPTH deep at most, one more is an error the program can catch, on the jit
+// (where calls are on the thread's stack) and the interpreter alike
+
+use std::collections::HashMap;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Error, Program, Word, MAX_DEPTH};
+
+// x after the program ran on the jit and then the interpreter, or the error it stopped with
+fn both(src: &str) -> [Result<Word, Error>; 2] {
+    let program = Program::parse(src).unwrap();
+
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap();
+    let jit = unsafe { engine.run() }.map(|_| engine.get("x").unwrap());
+
+    let mut vars = HashMap::new();
+    let interp = unsafe { program.interpret(BorrowingModuleProvider, &mut vars).unwrap() }.map(|_| vars["x"]);
+    [jit, interp]
+}
+
+// down n calls itself n more times, the call that would go too deep is caught by its caller
+fn down(n: Word) -> String {
+    format!(r#"
+        FN down n;
+          JEQ n, 0, done;
+          SUB n 1;
+          CALL down n r;
+          JERR deep;
+          ADD n r;
+          LABEL: done;
+          RET n;
+          LABEL: deep;
+          CALL errno e;
+          RET e;
+        END;
+        CALL down {n} x;
+    "#)
+}
+
+#[test]
+fn deep() {
+    // main's call and the ones after it, MAX_DEPTH of them going at once
+    for x in both(&down(MAX_DEPTH - 1)) {
+        assert_eq!(x.unwrap(), (MAX_DEPTH - 1) * (MAX_DEPTH - 2) / 2);
+    }
+
+    // down 0 is one too many, down 1 gets the error instead
+    for x in both(&down(MAX_DEPTH)) {
+        assert_eq!(x.unwrap(), Error::TOO_DEEP + MAX_DEPTH * (MAX_DEPTH - 1) / 2);
+    }
+}
+
+#[test]
+fn uncaught() {
+    let src = "FN f n; CALL f n r; RET r; END; CALL f 0 x;";
+    for e in both(src) {
+        let e = e.unwrap_err();
+        assert_eq!(e.code, Error::TOO_DEEP);
+        assert_eq!(e.message, format!("calls went more than {MAX_DEPTH} deep"));
+        assert_eq!(e.at, Some(1));
+    }
+
+    // every call it made is over, so the next run can go just as deep
+    let mut engine = Program::parse(src).unwrap().compile(BorrowingModuleProvider).unwrap();
+    for _ in 0..2 {
+        assert_eq!(unsafe { engine.run() }.unwrap_err().at, Some(1));
+    }
+}
@@ -0,0 +1,102 @@
+// the check pass finds what the backends would only find by crashing, each diagnostic points at its instruction
+
+use rba::modules::BorrowingModuleProvider;
+use rba::{Options, Program, Sandbox};
+
+// every diagnostic as its message, where it starts, how long it is and whether it's an error
+fn check(src: &str) -> Vec<(String, (usize, usize, usize), bool)> {
+    checked(Program::parse(src).unwrap())
+}
+
+fn checked(program: Program) -> Vec<(String, (usize, usize, usize), bool)> {
+    program.check(&BorrowingModuleProvider).into_iter().map(|d| (d.message.clone(), (d.line, d.column, d.len), d.is_error())).collect()
+}
+
+fn error(message: &str, at: (usize, usize, usize)) -> (String, (usize, usize, usize), bool) {
+    (message.to_string(), at, true)
+}
+
+fn warning(message: &str, at: (usize, usize, usize)) -> (String, (usize, usize, usize), bool) {
+    (message.to_string(), at, false)
+}
+
+#[test]
+fn labels() {
+    let src = "LABEL: a;\nJMP b;\nLABEL: a;\nJZ 1 a;";
+    assert_eq!(check(src), [
+        error("jump to undefined label `b`", (2, 1, 5)),
+        error("label `a` is defined more than once", (3, 1, 8))
+    ]);
+
+    let program = Program::parse(src).unwrap();
+    let duplicate = &program.check(&BorrowingModuleProvider)[1];
+    assert_eq!(duplicate.notes, ["first defined on line 1"]);
+}
+
+#[test]
+fn calls() {
+    let src = "FN f a, b; RET a; END;\nCALL f 1 x;\nCALL no_such_function_anywhere 1;\nCALL write 0, \"hi\", 2;";
+    assert_eq!(check(src), [
+        error("`f` takes 2 argument(s) but 1 were given", (2, 1, 10)),
+        error("unknown function `no_such_function_anywhere`", (3, 1, 32)),
+        error("`write` is from module `io`, which is not included", (4, 1, 21))
+    ]);
+}
+
+#[test]
+fn parameters() {
+    assert_eq!(check("FN f a, b, a; RET a; END;\nCALL f 1, 2, 3 r;"), [error("parameter `a` of `f` is named more than once", (1, 1, 12))]);
+}
+
+#[test]
+fn module_arity() {
+    let src = "INC io;\nCALL write 0, \"hi\";\nCALL stdout 1 h;";
+    assert_eq!(check(src), [
+        error("`write` takes 3 argument(s) but 2 were given", (2, 1, 18)),
+        error("`stdout` takes 0 argument(s) but 1 were given", (3, 1, 15))
+    ]);
+}
+
+#[test]
+fn handles() {
+    let src = "INC io;\nCALL close_file \"f.txt\";";
+    assert_eq!(check(src), [error("argument 1 of `close_file` is a handle, not a string", (2, 1, 23))]);
+
+    // every io function that gives back a handle
+    let program = Program::parse(src).unwrap();
+    let note = &program.check(&BorrowingModuleProvider)[0].notes[0];
+    for open in ["stdout", "stderr", "stdin", "open_file", "open_read", "open_append", "open_rw", "open_fd", "dir_open"] {
+        assert!(note.contains(&format!("`{open}`")), "{note}");
+    }
+}
+
+#[test]
+fn unreachable() {
+    let src = "JMP end;\nOUT 1;\nLABEL: end;\nFN f; RET 0; OUT 2; END;";
+    assert_eq!(check(src), [
+        warning("unreachable code", (2, 1, 5)),
+        warning("unreachable code", (4, 14, 5))
+    ]);
+}
+
+#[test]
+fn addresses() {
+    let src = "INC io;\nCALL write 0, 8, 1;";
+    assert_eq!(check(src), [warning("argument 2 of `write` is an address, `8` is unlikely to be one", (2, 1, 18))]);
+
+    // in a sandbox 8 is where the first string is
+    let mut program = Program::parse(src).unwrap();
+    program.set_options(Options { sandbox: Some(Sandbox::new(64).allow("write")), ..Options::default() });
+    assert_eq!(checked(program), []);
+}
+
+#[test]
+fn ir() {
+    let ir = Program::parse("FN f a; RET a; END; CALL f 1 x; OUT x;").unwrap().ir(BorrowingModuleProvider).unwrap();
+    assert!(ir.starts_with("; main\n"));
+    assert!(ir.contains("; f\n"));
+
+    let errors = Program::parse("JMP nowhere;").unwrap().ir(BorrowingModuleProvider).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "jump to undefined label `nowhere`");
+}
@@ -0,0 +1,125 @@
+// the `rba` binary, its commands and options
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+// runs `rba` with the program given on stdin as `-`
+fn rba(args: &[&str], src: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rba")).args(args).arg("-")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(src.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(out: &Output) -> &str {
+    std::str::from_utf8(&out.stdout).unwrap()
+}
+
+fn stderr(out: &Output) -> &str {
+    std::str::from_utf8(&out.stderr).unwrap()
+}
+
+#[test]
+fn run() {
+    let src = "MOV 1 a; OUT a; ADD a 2; OUT a;";
+    for args in [&[][..], &["run"], &["run", "--backend", "jit"], &["run", "--backend", "interp"]] {
+        let out = rba(args, src);
+        assert!(out.status.success());
+        assert_eq!(stdout(&out), "1\n3\n");
+    }
+}
+
+#[test]
+fn vars() {
+    let out = rba(&["run", "--backend", "interp", "--vars"], "MOV 7 a;");
+    assert!(out.status.success());
+    assert!(stderr(&out).contains("a = 7"));
+}
+
+#[test]
+fn check() {
+    let out = rba(&["check"], "OUT 1;");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "");
+
+    let out = rba(&["check"], "JMP nowhere;");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("jump to undefined label `nowhere`"));
+
+    // a program with errors doesn't get to run either
+    let out = rba(&["run"], "OUT 1; JMP nowhere;");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stdout(&out), "");
+}
+
+#[test]
+fn dump() {
+    let out = rba(&["dump-ast"], "OUT 5;");
+    assert!(out.status.success());
+    assert!(stdout(&out).starts_with("[\n    Output("));
+
+    let out = rba(&["dump-ir"], "FN f; RET 0; END; OUT 5;");
+    assert!(out.status.success());
+    assert!(stdout(&out).starts_with("; main\n"));
+    assert!(stdout(&out).contains("; f\n"));
+}
+
+#[test]
+fn time() {
+    let out = rba(&["--time"], "OUT 1;");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "1\n");
+    for phase in ["read", "parse", "check", "compile", "run"] {
+        assert!(stderr(&out).lines().any(|l| l.starts_with(&format!("{phase}: "))), "no `{phase}` in {}", stderr(&out));
+    }
+
+    let out = rba(&["check", "--time"], "OUT 1;");
+    assert!(!stderr(&out).contains("run: "));
+}
+
+#[test]
+fn parse_error() {
+    let out = rba(&[], "FOO;");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).starts_with("error: "));
+}
+
+#[test]
+fn usage() {
+    for args in [&["--bogus"][..], &["--backend", "gpu"], &["build"], &["--allow", "printc"], &["run", "extra"]] {
+        let out = rba(args, "");
+        assert_eq!(out.status.code(), Some(2), "{args:?}");
+        assert!(stderr(&out).contains("usage: rba"));
+    }
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rba")).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("no program given"));
+}
+
+// only the directories in RBA_MODULE_PATH are searched for `INC name`, never the working directory
+#[test]
+#[cfg(unix)]
+fn module_path() {
+    let built = Command::new(env!("CARGO")).args(["build", "--example", "math"]).output().unwrap();
+    assert!(built.status.success());
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/debug/examples");
+    let src = "INC math; CALL gcd 12, 18 g; OUT g;";
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rba")).args(["check", "-"]).current_dir(&examples).env_remove("RBA_MODULE_PATH")
+        .stdin(Stdio::piped()).stderr(Stdio::piped()).spawn().and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(src.as_bytes())?;
+            child.wait_with_output()
+        }).unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("unknown module `math`"));
+    assert!(stderr(&out).contains("RBA_MODULE_PATH isn't set"));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rba")).arg("-").env("RBA_MODULE_PATH", &examples)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(src.as_bytes()).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "6\n");
+}
@@ -0,0 +1,318 @@
+// runs the examples and a generated corpus of programs with both the JIT and the interpreter,
+// any difference in what they print, how they exit or what main's variables end up as fails
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug)]
+struct Run {
+    status: Option<i32>,
+    stdout: String,
+    vars: Vec<String>,
+    // not compared, only printed to show what went wrong
+    #[allow(dead_code)]
+    stderr: String
+}
+
+impl PartialEq for Run {
+    fn eq(&self, other: &Run) -> bool {
+        (self.status, &self.stdout, &self.vars) == (other.status, &other.stdout, &other.vars)
+    }
+}
+
+fn tmp(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+fn run(file: &Path, backend: &str, stdin: &str, ignore: &[&str]) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rba"))
+        .args(["run", "--vars", "--backend", backend])
+        .arg(file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    let stdout = drain(child.stdout.take().unwrap());
+    let stderr = drain(child.stderr.take().unwrap());
+    let status = wait(&mut child, file, backend);
+
+    let stdout = stdout.join().unwrap();
+    let stderr = stderr.join().unwrap();
+
+    // pointers and handles differ from run to run
+    let vars = stderr.lines()
+        .filter_map(|l| l.split_once(" = "))
+        .filter(|(name, value)| !ignore.contains(name) && value.chars().all(|c| c.is_ascii_digit()))
+        .map(|(name, value)| format!("{name} = {value}"))
+        .collect();
+
+    Run { status, stdout, vars, stderr }
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut out = Vec::new();
+        pipe.read_to_end(&mut out).unwrap();
+        String::from_utf8_lossy(&out).into_owned()
+    })
+}
+
+fn wait(child: &mut Child, file: &Path, backend: &str) -> Option<i32> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.code();
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            panic!("`{}` did not finish with the {backend} backend", file.display());
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+// both runs, or what went differently
+fn compare(file: &Path, stdin: &str, ignore: &[&str]) -> Result<[Run; 2], String> {
+    let jit = run(file, "jit", stdin, ignore);
+    let interp = run(file, "interp", stdin, ignore);
+
+    if jit == interp {
+        Ok([jit, interp])
+    } else {
+        Err(format!("`{}` differs\njit: {jit:#?}\ninterp: {interp:#?}", file.display()))
+    }
+}
+
+#[test]
+fn examples() {
+    let mut files: Vec<PathBuf> = fs::read_dir("examples").unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "rbasm"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty());
+
+    let mut failures = Vec::new();
+    for file in &files {
+        let name = file.file_name().unwrap().to_str().unwrap();
+        let result = match name {
+            "echo.rbasm" => compare(file, "hello\n", &["Z"]),
+            "fib.rbasm" => compare(file, "10\n", &[]),
+            "files.rbasm" => compare(file, "", &["buf"]),
+            // reads a line at a time from a terminal, from a pipe the first read gets everything
+            "txt.rbasm" => continue,
+            _ => compare(file, "", &[])
+        };
+
+        match result {
+            Ok([jit, _]) if jit.status != Some(0) => failures.push(format!("`{name}` failed with both backends: {jit:#?}")),
+            Ok(_) => { }
+            Err(e) => failures.push(e)
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[test]
+fn corpus() {
+    let seed = env::var("RBA_DIFF_SEED").ok().and_then(|s| s.parse().ok()).unwrap_or(0x5eed_1234_abcd_0001);
+    let cases = env::var("RBA_DIFF_CASES").ok().and_then(|s| s.parse().ok()).unwrap_or(100);
+
+    let mut gen = Gen { state: seed, labels: 0 };
+    let mut failures = Vec::new();
+    for case in 0..cases {
+        let file = tmp(&format!("corpus-{case}.rbasm"));
+        fs::write(&file, gen.program()).unwrap();
+
+        match compare(&file, "", &["mem", "p"]) {
+            Ok([jit, _]) if jit.status != Some(0) => failures.push(format!("`{}` failed with both backends: {jit:#?}", file.display())),
+            Ok(_) => { }
+            Err(e) => failures.push(e)
+        }
+    }
+
+    assert!(failures.is_empty(), "seed {seed}\n{}", failures.join("\n\n"));
+}
+
+const VARS: &[&str] = &["a", "b", "c", "d", "e", "f"];
+const BINARY: &[&str] = &["ADD", "SUB", "MUL", "AND", "OR", "XOR", "SHL", "SHR", "SAR", "ROL", "ROR"];
+const DIVISION: &[&str] = &["DIV", "MOD", "SDIV", "SMOD"];
+const UNARY: &[&str] = &["NEG", "NOT", "POPCNT", "CLZ", "CTZ"];
+const JUMPS: &[&str] = &["JEQ", "JNE", "JLT", "JLE", "JGT", "JGE", "JSLT", "JSLE", "JSGT", "JSGE"];
+const WIDTHS: &[&str] = &["B", "H", "W", "D", ""];
+// p points somewhere in the first 56 bytes of a 64 byte buffer, so 8 byte reads stay inside
+const BUFFER: usize = 64;
+
+// programs that always finish and never divide by zero, the only thing left to differ is the backend
+struct Gen {
+    state: u64,
+    labels: usize
+}
+
+// where statements end up, only main has the buffer and functions can only call the ones before them
+struct Scope {
+    params: &'static [&'static str],
+    memory: bool,
+    functions: usize
+}
+
+impl Gen {
+    // xorshift64*
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    fn constant(&mut self) -> String {
+        match self.below(6) {
+            0 => self.below(100).to_string(),
+            1 => format!("-{}", self.below(100)),
+            2 => self.pick(&["0", "1", "63", "64", "9223372036854775808", "18446744073709551615"]).to_string(),
+            _ => self.next().to_string()
+        }
+    }
+
+    fn var(&mut self, scope: &Scope) -> String {
+        if scope.memory && self.below(5) == 0 {
+            return format!("{}&p", self.pick(WIDTHS));
+        }
+        if !scope.params.is_empty() && self.below(3) == 0 {
+            return self.pick(scope.params).to_string();
+        }
+        self.pick(VARS).to_string()
+    }
+
+    fn val(&mut self, scope: &Scope) -> String {
+        if self.below(2) == 0 { self.constant() } else { self.var(scope) }
+    }
+
+    fn statements(&mut self, scope: &Scope, depth: usize, out: &mut Vec<String>) {
+        for _ in 0..1 + self.below(12) {
+            self.statement(scope, depth, out);
+        }
+    }
+
+    fn statement(&mut self, scope: &Scope, depth: usize, out: &mut Vec<String>) {
+        let kinds = if depth < 2 { 11 } else { 8 };
+        match self.below(kinds) {
+            0 | 1 => {
+                let (op, var, val) = (self.pick(BINARY), self.var(scope), self.val(scope));
+                out.push(format!("{op} {var} {val};"));
+            }
+            2 => {
+                let (op, var, by) = (self.pick(DIVISION), self.var(scope), 1 + self.below(1000));
+                out.push(format!("{op} {var} {by};"));
+            }
+            3 => {
+                let (op, var, val) = (self.pick(UNARY), self.var(scope), self.val(scope));
+                out.push(format!("{op} {var} {val};"));
+            }
+            4 => {
+                let (val, var) = (self.val(scope), self.var(scope));
+                out.push(format!("MOV {val} {var};"));
+            }
+            5 => {
+                let (a, b) = (self.var(scope), self.var(scope));
+                out.push(format!("SWAP {a} {b};"));
+            }
+            6 if scope.memory => {
+                out.push(format!("MOV mem p; ADD p {};", self.below(BUFFER - 7)));
+            }
+            6 | 7 => {
+                let val = self.val(scope);
+                out.push(format!("OUT {val};"));
+            }
+            8 => {
+                // the counter isn't one of VARS so nothing in the body touches it,
+                // variable names can't have digits so it's i and the number spelled in letters
+                let n = self.label();
+                let i: String = n.to_string().bytes().map(|d| (d - b'0' + b'a') as char).collect();
+                out.push(format!("MOV {} i{i};", 1 + self.below(5)));
+                out.push(format!("LABEL: loop{n};"));
+                self.statements(scope, depth + 1, out);
+                out.push(format!("SUB i{i} 1;"));
+                out.push(format!("JNZ i{i} loop{n};"));
+            }
+            9 => {
+                let n = self.label();
+                let jump = match self.below(3) {
+                    0 => format!("JZ {} skip{n};", self.val(scope)),
+                    1 => format!("JNZ {} skip{n};", self.val(scope)),
+                    _ => format!("{} {}, {}, skip{n};", self.pick(JUMPS), self.val(scope), self.val(scope))
+                };
+                out.push(jump);
+                self.statements(scope, depth + 1, out);
+                out.push(format!("LABEL: skip{n};"));
+            }
+            _ if scope.functions > 0 => {
+                let f = self.below(scope.functions);
+                let (a, b, var) = (self.val(scope), self.val(scope), self.var(scope));
+                out.push(format!("CALL f{f} {a}, {b} {var};"));
+            }
+            _ => {
+                let (val, var) = (self.val(scope), self.var(scope));
+                out.push(format!("MOV {val} {var};"));
+            }
+        }
+    }
+
+    fn program(&mut self) -> String {
+        let mut out = Vec::new();
+        let functions = self.below(3);
+
+        for f in 0..functions {
+            let scope = Scope { params: &["x", "y"], memory: false, functions: f };
+            out.push(format!("FN f{f} x, y;"));
+            self.statements(&scope, 0, &mut out);
+            let ret = self.val(&scope);
+            out.push(format!("RET {ret};"));
+            out.push(String::from("END;"));
+        }
+
+        out.push(format!("CALL malloc {BUFFER} mem;"));
+        for offset in (0..BUFFER).step_by(8) {
+            out.push(format!("MOV mem p; ADD p {offset}; MOV 0 &p;"));
+        }
+        out.push(String::from("MOV mem p;"));
+        for var in VARS {
+            let val = self.constant();
+            out.push(format!("MOV {val} {var};"));
+        }
+
+        let scope = Scope { params: &[], memory: true, functions };
+        self.statements(&scope, 0, &mut out);
+        self.statements(&scope, 0, &mut out);
+
+        out.push(String::from("MOV mem p;"));
+        for _ in 0..BUFFER / 8 {
+            out.push(String::from("OUT &p; ADD p 8;"));
+        }
+
+        out.join("\n") + "\n"
+    }
+}
@@ -0,0 +1,70 @@
+// modules loaded from shared libraries with INC, examples/math.rs is the one they load
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Capture, Program, Redirect};
+
+// cargo test builds the examples already, this is for running only this file
+#[cfg(unix)]
+fn math() -> PathBuf {
+    let built = Command::new(env!("CARGO")).args(["build", "--example", "math"]).output().unwrap();
+    assert!(built.status.success());
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/examples").join(format!("{}math{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX))
+}
+
+#[test]
+#[cfg(unix)]
+fn path() {
+    let src = format!(r#"
+        INC "{}";
+        CALL gcd 462, 1071 g;
+        OUT g;
+        CALL div 7, 0 q;
+        JERR caught;
+        OUT 0;
+        LABEL: caught;
+        CALL errno e;
+        OUT e;
+    "#, math().display());
+    let program = Program::parse(src).unwrap();
+    assert!(program.check(&BorrowingModuleProvider).is_empty());
+
+    let out = Capture::new();
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone());
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(out.take(), b"21\n65539\n");
+
+    let mut bytecode = program.bytecode(BorrowingModuleProvider).unwrap();
+    bytecode.set_stdout(out.clone());
+    unsafe { bytecode.run(&mut HashMap::new()) }.unwrap();
+    assert_eq!(out.text(), "21\n65539\n");
+}
+
+#[test]
+fn not_found() {
+    let program = Program::parse("INC \"/no/such/dir/libnothing.so\";\nINC nothing_by_that_name;").unwrap();
+    let diags = program.check(&BorrowingModuleProvider);
+    assert_eq!(diags.len(), 2);
+
+    assert_eq!(diags[0].message, "unknown module `/no/such/dir/libnothing.so`");
+    assert!(diags[0].notes[0].starts_with("can't load `/no/such/dir/libnothing.so`"), "{}", diags[0].notes[0]);
+    assert_eq!(diags[1].message, "unknown module `nothing_by_that_name`");
+    assert_eq!(diags[1].line, 2);
+    assert_eq!(diags[1].notes.len(), 1);
+
+    assert!(program.compile(BorrowingModuleProvider).is_err());
+    assert!(program.bytecode(BorrowingModuleProvider).is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn not_ahead_of_time() {
+    let program = Program::parse(format!("INC std;\nINC \"{}\";\nCALL gcd 4, 6 g;", math().display())).unwrap();
+    let errors = program.object(BorrowingModuleProvider).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].message.ends_with("can't be compiled ahead of time"));
+    assert_eq!(errors[0].line, 2);
+}
@@ -0,0 +1,130 @@
+// errors a program doesn't catch end the run and come back from it, with main's variables kept
+
+use std::collections::HashMap;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Capture, Error, Options, Program, Redirect};
+
+const SRC: &str = r#"
+    INC io;
+    MOV 1 a;
+    CALL close_file 12345;
+    MOV 2 a;
+"#;
+
+#[test]
+fn jit() {
+    let out = Capture::new();
+    let mut engine = Program::parse(SRC).unwrap().compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone());
+
+    let e = unsafe { engine.run() }.unwrap_err();
+    assert_eq!(e.code, Error::BAD_HANDLE);
+    assert_eq!(e.message, "io::close_file: 12345 is not an open file");
+    assert_eq!(engine.get("a"), Some(1));
+
+    // the next run starts without it
+    engine.set("a", 0);
+    assert!(unsafe { engine.run() }.is_err());
+}
+
+#[test]
+fn interpreter() {
+    let mut vars = HashMap::new();
+    let e = unsafe { Program::parse(SRC).unwrap().interpret(BorrowingModuleProvider, &mut vars).unwrap() }.unwrap_err();
+    assert_eq!(e.code, Error::BAD_HANDLE);
+    assert_eq!(vars["a"], 1);
+}
+
+#[test]
+fn caught() {
+    let src = r#"
+        INC io;
+        CALL close_file 12345 r;
+        CALL errno e;
+        CALL malloc 8 buf;
+        CALL read 0, buf, 8;
+        JERR bad;
+        MOV 1 ok;
+        LABEL: bad;
+    "#;
+
+    let mut engine = Program::parse(src).unwrap().compile(BorrowingModuleProvider).unwrap();
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(engine.get("r"), Some(u64::MAX));
+    assert_eq!(engine.get("e"), Some(Error::BAD_HANDLE));
+    assert_eq!(engine.get("ok"), Some(0));
+}
+
+#[test]
+fn division() {
+    let src = r#"
+        FN half x;
+          MOV 10 y;
+          DIV y x;
+          RET y;
+        END;
+        MOV 0 z;
+        MOV 7 a;
+        DIV a z;
+        CALL errno e;
+        CALL half 5 h;
+        CALL half z g;
+    "#;
+    let program = Program::parse(src).unwrap();
+
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap();
+    let e = unsafe { engine.run() }.unwrap_err();
+    assert_eq!((e.code, e.at, e.line), (Error::DIV_ZERO, Some(2), Some(4)));
+    assert_eq!((engine.get("a"), engine.get("e"), engine.get("h")), (Some(7), Some(Error::DIV_ZERO), Some(2)));
+
+    let mut vars = HashMap::new();
+    let e = unsafe { program.interpret(BorrowingModuleProvider, &mut vars).unwrap() }.unwrap_err();
+    assert_eq!((e.code, e.at, e.line), (Error::DIV_ZERO, Some(2), Some(4)));
+    assert_eq!((vars["a"], vars["e"], vars["h"]), (7, Error::DIV_ZERO, 2));
+}
+
+#[test]
+fn checked() {
+    let src = r#"
+        CALL malloc 16 p;
+        MOV p q;
+        ADD q 8;
+        MOV 6 &q;
+        ADD q 4;
+        MOV 1 &q;
+        CALL errno e;
+        MOV "hi" s;
+        MOV B&s c;
+        CALL free p;
+        MOV &p x;
+        MOV 1 after;
+    "#;
+    let mut program = Program::parse(src).unwrap();
+    program.set_options(Options { checked: true, ..Options::default() });
+
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap();
+    let e = unsafe { engine.run() }.unwrap_err();
+    assert_eq!((e.code, e.at), (Error::BAD_ADDRESS, Some(10)));
+    assert_eq!((engine.get("e"), engine.get("c"), engine.get("after")), (Some(Error::BAD_ADDRESS), Some(104), Some(0)));
+
+    let mut vars = HashMap::new();
+    let e = unsafe { program.interpret(BorrowingModuleProvider, &mut vars).unwrap() }.unwrap_err();
+    assert_eq!((e.code, e.at), (Error::BAD_ADDRESS, Some(10)));
+    assert_eq!((vars["e"], vars["c"], vars["after"]), (Error::BAD_ADDRESS, 104, 0));
+}
+
+#[test]
+fn invalid() {
+    // a label that isn't there and a FN called with the wrong number of arguments
+    let src = r#"
+        FN f a, b; RET a; END;
+        CALL f 1 x;
+        JMP nowhere;
+    "#;
+    let program = Program::parse(src).unwrap();
+
+    assert_eq!(program.compile(BorrowingModuleProvider).err().unwrap().len(), 2);
+    assert_eq!(program.object(BorrowingModuleProvider).err().unwrap().len(), 2);
+    assert_eq!(program.bytecode(BorrowingModuleProvider).err().unwrap().len(), 2);
+    assert!(unsafe { program.interpret(BorrowingModuleProvider, &mut HashMap::new()) }.is_err());
+}
@@ -0,0 +1,129 @@
+// io's handle table, a handle stops working once it's closed, even when its slot is used again,
+// and whatever a run leaves open is closed when it ends
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Capture, Program, Redirect, Word};
+
+const BAD_HANDLE: Word = 0x1_0001;
+
+fn tmp(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    path.to_str().unwrap().to_string()
+}
+
+// OUTs of the program, on the jit and then the interpreter
+fn both(src: &str) -> [Vec<Word>; 2] {
+    let program = Program::parse(src).unwrap();
+    let words = |out: Vec<u8>| String::from_utf8(out).unwrap().lines().map(|l| l.parse().unwrap()).collect();
+
+    let out = Capture::new();
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone());
+    unsafe { engine.run() }.unwrap();
+    let jit = words(out.take());
+
+    let mut bytecode = program.bytecode(BorrowingModuleProvider).unwrap();
+    bytecode.set_stdout(out.clone());
+    unsafe { bytecode.run(&mut HashMap::new()) }.unwrap();
+
+    [jit, words(out.take())]
+}
+
+#[test]
+fn stale_generation() {
+    let (a, b) = (tmp("stale_a"), tmp("stale_b"));
+    let src = format!(r#"
+        INC io;
+        CALL open_file "{a}", {} old;
+        CALL close_file old;
+        CALL open_file "{b}", {} new;
+        OUT old;
+        OUT new;
+        CALL write old, "x", 1 n;
+        JERR stale;
+        OUT 0;
+        LABEL: stale;
+        CALL errno e;
+        OUT e;
+        CALL write new, "y", 1 n;
+        OUT n;
+        CALL close_file new;
+    "#, a.len(), b.len());
+
+    for out in both(&src) {
+        let [old, new, e, n] = out[..] else { panic!("{out:?}") };
+        // the same slot a generation later
+        assert_eq!(old as u32, new as u32);
+        assert_eq!(new >> 32, (old >> 32) + 1);
+        assert_eq!((e, n), (BAD_HANDLE, 1));
+    }
+    assert_eq!(fs::read_to_string(&a).unwrap(), "");
+    assert_eq!(fs::read_to_string(&b).unwrap(), "y");
+}
+
+#[test]
+fn double_close() {
+    let file = tmp("double_close");
+    let src = format!(r#"
+        INC io;
+        CALL open_file "{file}", {} h;
+        CALL close_file h r;
+        OUT r;
+        CALL close_file h r;
+        JERR twice;
+        OUT 0;
+        LABEL: twice;
+        CALL errno e;
+        OUT e;
+        CALL close_file 0 r;
+        JERR standard;
+        OUT 0;
+        LABEL: standard;
+        CALL errno e;
+        OUT e;
+    "#, file.len());
+
+    // stdout isn't in the table to close either
+    assert_eq!(both(&src), [[0, BAD_HANDLE, BAD_HANDLE], [0, BAD_HANDLE, BAD_HANDLE]]);
+}
+
+#[test]
+fn closed_after_the_run() {
+    let file = tmp("closed_after_the_run");
+    let src = format!(r#"
+        INC io;
+        JNZ h again;
+        CALL open_file "{file}", {} h;
+        CALL write h, "left open", 9 n;
+        LABEL: again;
+        CALL write h, "!", 1 n;
+        JERR gone;
+        OUT n;
+        JMP end;
+        LABEL: gone;
+        CALL errno e;
+        OUT e;
+        LABEL: end;
+    "#, file.len());
+
+    let out = Capture::new();
+    let mut engine = Program::parse(src).unwrap().compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone());
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(out.take(), b"1\n");
+    assert_eq!(fs::read_to_string(&file).unwrap(), "left open!");
+
+    // nothing has the file open anymore
+    if cfg!(target_os = "linux") {
+        let open = fs::read_dir("/proc/self/fd").unwrap().filter_map(|fd| fs::read_link(fd.unwrap().path()).ok());
+        assert!(!open.into_iter().any(|path| path.to_str() == Some(&file)));
+    }
+
+    // h is still around from the last run, but what it was a handle for isn't
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(out.text(), format!("{BAD_HANDLE}\n"));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "left open!");
+}
@@ -0,0 +1,93 @@
+// programs with fuel stop after running that many instructions instead of going on forever
+
+use std::collections::HashMap;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Capture, Error, Options, Program, Redirect};
+
+fn fueled(src: &str, fuel: u64) -> Program {
+    let mut program = Program::parse(src).unwrap();
+    program.set_options(Options { fuel: Some(fuel), ..Options::default() });
+    program
+}
+
+const LOOP: &str = r#"
+    FN step x;
+      ADD x 1;
+      RET x;
+    END;
+    MOV 0 i;
+    LABEL: loop;
+    CALL step i i;
+    JERR loop;
+    JZ 0 loop;
+"#;
+
+#[test]
+fn jit() {
+    let mut engine = fueled(LOOP, 1000).compile(BorrowingModuleProvider).unwrap();
+    let e = unsafe { engine.run() }.unwrap_err();
+    assert_eq!(e.code, Error::OUT_OF_FUEL);
+    assert_eq!(e.message, "fuel exhausted after 1000 instructions");
+    assert_eq!(engine.get("i"), Some(200));
+
+    // every run gets all of it again
+    engine.set("i", 0);
+    assert!(unsafe { engine.run() }.is_err());
+    assert_eq!(engine.get("i"), Some(200));
+}
+
+#[test]
+fn interpreter() {
+    let mut vars = HashMap::new();
+    let e = unsafe { fueled(LOOP, 1000).interpret(BorrowingModuleProvider, &mut vars).unwrap() }.unwrap_err();
+    assert_eq!(e.code, Error::OUT_OF_FUEL);
+    assert_eq!(e.message, "fuel exhausted after 1000 instructions");
+    assert_eq!((e.at, e.line), (Some(7), Some(10)));
+    assert_eq!(vars["i"], 200);
+}
+
+#[test]
+fn enough() {
+    let src = "MOV 3 n; LABEL: loop; SUB n 1; JNZ n loop; MOV 1 done;";
+
+    let mut engine = fueled(src, 8).compile(BorrowingModuleProvider).unwrap();
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(engine.get("done"), Some(1));
+
+    let mut vars = HashMap::new();
+    unsafe { fueled(src, 8).interpret(BorrowingModuleProvider, &mut vars).unwrap() }.unwrap();
+    assert_eq!(vars["done"], 1);
+    assert!(unsafe { fueled(src, 7).interpret(BorrowingModuleProvider, &mut HashMap::new()).unwrap() }.is_err());
+}
+
+// what both backends make of the same budget, the error and what got printed
+fn both(src: &str, fuel: u64) -> [(Option<String>, String); 2] {
+    let program = fueled(src, fuel);
+    let out = Capture::new();
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone());
+    let jit = (unsafe { engine.run() }.err().map(|e| e.message), out.text());
+
+    let out = Capture::new();
+    let mut bytecode = program.bytecode(BorrowingModuleProvider).unwrap();
+    bytecode.set_stdout(out.clone());
+    let interp = (unsafe { bytecode.run(&mut HashMap::new()) }.err().map(|e| e.message), out.text());
+    [jit, interp]
+}
+
+#[test]
+fn nothing_without_fuel() {
+    // the jit takes fuel before OUT and calls, not only at the end of what runs without jumping
+    let stopped = |n: u64| (Some(format!("fuel exhausted after {n} instructions")), String::new());
+    assert_eq!(both("OUT 1;", 0), [stopped(0), stopped(0)]);
+    assert_eq!(both("MOV 1 a; CALL printc 65; MOV 2 b;", 1), [stopped(1), stopped(1)]);
+    assert_eq!(both("OUT 1;", 1), [(None, String::from("1\n")), (None, String::from("1\n"))]);
+}
+
+#[test]
+fn recursion() {
+    // every call takes fuel, this runs out well before calls go MAX_DEPTH deep
+    let src = "FN f n; CALL f n r; RET r; END; CALL f 1 x;";
+    let stopped = (Some(String::from("fuel exhausted after 5000 instructions")), String::new());
+    assert_eq!(both(src, 5000), [stopped.clone(), stopped]);
+}
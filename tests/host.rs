@@ -0,0 +1,71 @@
+// rust closures registered with a Host, called by programs on both backends
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use rba::{Capture, Host, Program, Redirect, Word};
+
+// runs the program on the jit and then the interpreter, with what it printed each time
+fn both(src: &str, host: &Host) -> [String; 2] {
+    let program = Program::parse(src).unwrap();
+
+    let out = Capture::new();
+    let mut engine = program.compile(host).unwrap();
+    engine.set_stdout(out.clone());
+    unsafe { engine.run() }.unwrap();
+    let jit = out.take();
+
+    let mut bytecode = program.bytecode(host).unwrap();
+    bytecode.set_stdout(out.clone());
+    unsafe { bytecode.run(&mut HashMap::new()) }.unwrap();
+
+    [String::from_utf8(jit).unwrap(), out.text()]
+}
+
+#[test]
+fn captured_state() {
+    let calls = Rc::new(Cell::new(0));
+    let seen = Rc::new(RefCell::new(Vec::new()));
+
+    let mut host = Host::new();
+    let counter = calls.clone();
+    let log = seen.clone();
+    host.register("count", move || { counter.set(counter.get() + 1); counter.get() })
+        .register("log", move |a: Word, b: Word| log.borrow_mut().push((a, b)));
+
+    let src = "INC host; RCALL count a; RCALL count b; CALL log a, b; OUT b;";
+    assert_eq!(both(src, &host), ["2\n", "4\n"]);
+    assert_eq!(calls.get(), 4);
+    assert_eq!(*seen.borrow(), [(1, 2), (3, 4)]);
+}
+
+#[test]
+fn arguments() {
+    let mut host = Host::new();
+    host.register("six", |a: Word, b: Word, c: Word, d: Word, e: Word, f: Word| a + 10 * b + 100 * c + 1000 * d + 10000 * e + 100000 * f);
+
+    // from main and from inside a FN, where the arguments come from the FN's own variables
+    let src = "INC host; FN g x; CALL six x, 2, 3, 4, 5, 6 r; RET r; END; CALL six 1, 2, 3, 4, 5, 6 a; OUT a; CALL g 9 b; OUT b;";
+    assert_eq!(both(src, &host), ["654321\n654329\n"; 2]);
+}
+
+#[test]
+fn registered_again() {
+    let mut host = Host::new();
+    host.register("f", |a: Word| a + 1).register("f", |a: Word| a * 2);
+    assert_eq!(both("INC host; CALL f 21 a; OUT a;", &host), ["42\n"; 2]);
+
+    // check goes by the signature of the one registered last
+    host.register("f", |a: Word, b: Word| a - b);
+    let errors = Program::parse("INC host; CALL f 21 a;").unwrap().compile(&host).err().unwrap();
+    assert_eq!(errors[0].message, "`f` takes 2 argument(s) but 1 were given");
+}
+
+#[test]
+fn not_ahead_of_time() {
+    let mut host = Host::new();
+    host.register("f", |a: Word| a);
+
+    let errors = Program::parse("INC host; CALL f 1 a; OUT a;").unwrap().object(&host).unwrap_err();
+    assert_eq!(errors[0].message, "module `host` can't be compiled ahead of time");
+}
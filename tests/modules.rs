@@ -0,0 +1,77 @@
+// what #[module] generates, the signatures check goes by and the descriptor shared libraries export
+
+use std::ffi::CStr;
+use rba_runtime::{module, Addr, Error, FnSig, Kind, Len, Module, ModuleDescriptor, Std, Word, ABI_VERSION, IO};
+
+// the macro only goes by the name of the type
+#[repr(transparent)]
+pub struct Wp(Word);
+
+pub struct Sample;
+
+#[module(sample)]
+impl Sample {
+    fn nothing() { }
+    fn add(a: Word, b: Word) -> Word { a + b }
+    fn peek(at: Addr, handle: Wp) -> Result<Word, Error> { Ok(at + handle.0) }
+    fn fail(_code: Word) -> Result<(), Error> { Err(Error::new(Error::INVALID, "no")) }
+    fn fill(_at: Addr, len: Len) -> Word { len }
+}
+
+fn sig(params: usize, returns: bool, kinds: &'static [Kind]) -> FnSig {
+    FnSig { params, returns, kinds }
+}
+
+#[test]
+fn signatures() {
+    assert_eq!(Sample::NAME, "sample");
+    assert_eq!(Sample::SIGNATURES, [
+        ("nothing", sig(0, false, &[])),
+        ("add", sig(2, true, &[Kind::Word, Kind::Word])),
+        ("peek", sig(2, true, &[Kind::Addr, Kind::Wp])),
+        ("fail", sig(1, false, &[Kind::Word])),
+        ("fill", sig(2, true, &[Kind::Addr, Kind::Len]))
+    ]);
+
+    let names: Vec<_> = Sample::symbols().iter().map(|(name, _)| *name).collect();
+    assert_eq!(names, ["nothing", "add", "peek", "fail", "fill"]);
+
+    let add = Sample::symbols()[1].1;
+    let add: extern "C" fn(Word, Word) -> Word = unsafe { std::mem::transmute(add) };
+    assert_eq!(add(40, 2), 42);
+}
+
+#[test]
+fn descriptor() {
+    let descriptor: &ModuleDescriptor = &rba_module_sample;
+    assert_eq!(descriptor.abi, ABI_VERSION);
+    assert_eq!(unsafe { CStr::from_ptr(descriptor.name) }.to_str().unwrap(), "sample");
+
+    let functions = unsafe { std::slice::from_raw_parts(descriptor.functions, descriptor.len) };
+    let described: Vec<_> = functions.iter().map(|f| {
+        let name = unsafe { CStr::from_ptr(f.name) }.to_str().unwrap();
+        let kinds = unsafe { std::slice::from_raw_parts(f.kinds, f.params) };
+        (name, sig(f.params, f.returns, Box::leak(kinds.into())))
+    }).collect();
+    assert_eq!(described, Sample::SIGNATURES);
+
+    for (f, (_, ptr)) in functions.iter().zip(Sample::symbols()) {
+        assert_eq!(f.ptr, ptr);
+    }
+}
+
+#[test]
+fn runtime_modules() {
+    let find = |sigs: &[(&str, FnSig)], name: &str| sigs.iter().find(|(n, _)| *n == name).unwrap().1;
+
+    assert_eq!(Std::NAME, "std");
+    assert_eq!(find(Std::SIGNATURES, "printc"), sig(1, false, &[Kind::Word]));
+    assert_eq!(find(Std::SIGNATURES, "addr_8"), sig(1, true, &[Kind::Addr]));
+
+    assert_eq!(IO::NAME, "io");
+    assert_eq!(find(IO::SIGNATURES, "write"), sig(3, true, &[Kind::Wp, Kind::Addr, Kind::Len]));
+    assert_eq!(find(IO::SIGNATURES, "open_file"), sig(2, true, &[Kind::Addr, Kind::Len]));
+    assert_eq!(find(IO::SIGNATURES, "rename"), sig(4, true, &[Kind::Addr, Kind::Len, Kind::Addr, Kind::Len]));
+    assert_eq!(find(IO::SIGNATURES, "close_file"), sig(1, true, &[Kind::Wp]));
+    assert_eq!(IO::SIGNATURES.len(), IO::symbols().len());
+}
@@ -0,0 +1,92 @@
+// the parser points at what's wrong instead of panicking, with where it is and what would have fit
+
+use rba::asm::AsmIns;
+use rba::parser::asm_spanned;
+use rba::Diagnostic;
+
+fn error(src: &'static str) -> Diagnostic {
+    *asm_spanned(src.as_bytes()).unwrap_err()
+}
+
+#[test]
+fn location() {
+    let e = error("MOV 1 a;\nADD a 2\nOUT a;");
+    assert_eq!(e.message, "expected `;` after instruction");
+    assert_eq!((e.line, e.column, e.offset), (3, 1, 17));
+    assert_eq!(e.expected, [";"]);
+    assert_eq!(e.instruction.as_deref(), Some("ADD a 2 OUT a"));
+}
+
+#[test]
+fn operands() {
+    let e = error("MOV 1 a;\n  SWAP a 3;");
+    assert_eq!(e.message, "invalid operands for `SWAP`");
+    assert_eq!((e.line, e.column), (2, 10));
+    assert_eq!(e.expected, ["<VAR> <VAR>"]);
+    assert_eq!(e.instruction.as_deref(), Some("SWAP a 3"));
+}
+
+#[test]
+fn unknown() {
+    let e = error("MOV 1 a;\nPRINT a;");
+    assert_eq!(e.message, "unknown instruction `PRINT`");
+    assert_eq!((e.line, e.column, e.len), (2, 1, 5));
+    assert!(e.expected.iter().any(|m| m == "MOV"));
+    assert_eq!(e.instruction.as_deref(), Some("PRINT a"));
+}
+
+#[test]
+fn whole_mnemonic() {
+    // not a MOV with a stray `E`
+    let e = error("MOVE 1 a;");
+    assert_eq!(e.message, "unknown instruction `MOVE`");
+    assert_eq!((e.line, e.column), (1, 1));
+
+    let e = error("RETURN;");
+    assert_eq!(e.message, "unknown instruction `RETURN`");
+
+    let (ins, _) = asm_spanned(b"NOP;NOP/* c */;\nRET").unwrap();
+    assert_eq!(ins.len(), 3);
+}
+
+#[test]
+fn empty_statements() {
+    let (ins, spans) = asm_spanned(b";MOV 1 a;; ;\nOUT a;;").unwrap();
+    assert_eq!(ins.len(), 2);
+    assert_eq!(spans, [1..8, 13..18]);
+}
+
+#[test]
+fn comments() {
+    let src = b"CALL write 0 /* out */ , \"hi\", 2;\nJMP x// c\n;\nLABEL: x# c\n;\nJEQ 1 /**/ , 1 /**/ , x/* c */;\nFN f a /**/ , b; RET a; END;";
+    let (ins, _) = asm_spanned(src).unwrap();
+
+    assert!(matches!(&ins[0], AsmIns::Call(f, args, None) if f == "write" && args.len() == 3));
+    assert!(matches!(&ins[1], AsmIns::Jmp(l) if l == "x"));
+    assert!(matches!(&ins[2], AsmIns::Label(l) if l == "x"));
+    assert!(matches!(&ins[3], AsmIns::JCmp(_, _, _, l) if l == "x"));
+    assert!(matches!(&ins[4], AsmIns::Function(f, params, _) if f == "f" && params.len() == 2));
+}
+
+#[test]
+fn comment_after_end() {
+    let (ins, _) = asm_spanned(b"FN f a; RET a; END// done\n;FN g; RET 0; END/* x */;").unwrap();
+    assert!(matches!(&ins[0], AsmIns::Function(f, _, body) if f == "f" && body.len() == 1));
+    assert!(matches!(&ins[1], AsmIns::Function(f, _, _) if f == "g"));
+
+    assert_eq!(error("END# c\n;").message, "`END` without a matching `FN`");
+    assert_eq!(error("FOO// x\n;").message, "unknown instruction `FOO`");
+}
+
+#[test]
+fn snippet() {
+    let e = error("MOV 1 a;\nADD a;");
+    assert_eq!(e.to_string(), "\
+error: invalid operands for `ADD`
+ --> 2:6
+  |
+2 | ADD a;
+  |      ^
+  = expected: `<VAR> <VAL>`
+  = in instruction: `ADD a`");
+}
@@ -0,0 +1,132 @@
+// sandboxed programs only get at memory of their own and only call what they're allowed to
+
+use std::collections::HashMap;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Capture, Error, Options, Program, Redirect, Sandbox, Streams, Word};
+
+fn sandboxed(src: &str, sandbox: Sandbox) -> Program {
+    let mut program = Program::parse(src).unwrap();
+    program.set_options(Options { sandbox: Some(sandbox), ..Options::default() });
+    program
+}
+
+// runs with both backends, they have to agree on the variables and error
+fn run(program: &Program, names: &[&str]) -> (Vec<Word>, Option<Error>) {
+    let mut engine = program.compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(Capture::new());
+    let jit_error = unsafe { engine.run() }.err();
+    let jit: Vec<Word> = names.iter().map(|n| engine.get(n).unwrap()).collect();
+
+    let mut vars = HashMap::new();
+    let out = Capture::new();
+    let mut streams = Streams { stdout: Box::new(out), ..Streams::default() };
+    let error = rba::with_streams(&mut streams, || unsafe { program.interpret(BorrowingModuleProvider, &mut vars).unwrap() }).err();
+    let interp: Vec<Word> = names.iter().map(|n| vars[*n]).collect();
+
+    assert_eq!(jit, interp);
+    assert_eq!(jit_error.as_ref().map(|e| (e.code, e.at)), error.as_ref().map(|e| (e.code, e.at)));
+    (jit, error)
+}
+
+#[test]
+fn memory() {
+    let src = r#"
+        MOV "hi" s;
+        MOV B&s c;
+        CALL malloc 16 p;
+        MOV 7 &p;
+        MOV &p x;
+        CALL malloc 1000 big;
+        CALL free p;
+        CALL malloc 8 again;
+        MOV 1020 a;
+        MOV 1 &a;
+        CALL errno e;
+        MOV 100000 &a;
+        MOV 1 after;
+    "#;
+
+    let (vars, error) = run(&sandboxed(src, Sandbox::new(1024)), &["s", "c", "p", "x", "big", "again", "e", "after"]);
+    assert_eq!(vars, [8, 104, 16, 7, 0, 16, Error::OUT_OF_BOUNDS, 0]);
+    assert_eq!(error.unwrap().code, Error::OUT_OF_BOUNDS);
+}
+
+#[test]
+fn calls() {
+    let src = r#"
+        CALL labs -7 x;
+        CALL abs -3 y;
+        MOV 1 after;
+    "#;
+
+    // calls the sandbox doesn't allow never get as far as running
+    let program = sandboxed(src, Sandbox::new(64));
+    assert_eq!(program.check(&BorrowingModuleProvider).iter().filter(|d| d.is_error()).count(), 2);
+    assert_eq!(program.compile(BorrowingModuleProvider).err().unwrap().len(), 2);
+    assert_eq!(unsafe { program.interpret(BorrowingModuleProvider, &mut HashMap::new()) }.err().unwrap().len(), 2);
+
+    let (vars, error) = run(&sandboxed(src, Sandbox::new(64).allow("labs").allow("abs")), &["x", "y", "after"]);
+    assert_eq!(vars, [7, 3, 1]);
+    assert!(error.is_none());
+}
+
+#[test]
+fn too_small() {
+    let errors = |src: &str, memory: usize| -> Vec<String> {
+        let program = sandboxed(src, Sandbox::new(memory));
+        program.check(&BorrowingModuleProvider).into_iter().filter(|d| d.is_error()).map(|d| d.message).collect()
+    };
+
+    // nothing is ever at 0, so even a program without strings needs some
+    assert_eq!(errors("MOV 1 a;", 0), ["the sandbox's 0 bytes of memory are too small for the program's strings, they need 8"]);
+    assert!(sandboxed("MOV 1 a;", Sandbox::new(0)).compile(BorrowingModuleProvider).is_err());
+
+    let src = "MOV 1 a;\nMOV \"twenty five bytes long!!!\" s;";
+    let e = sandboxed(src, Sandbox::new(16)).check(&BorrowingModuleProvider).remove(0);
+    assert_eq!((e.message.as_str(), e.line), ("the sandbox's 16 bytes of memory are too small for the program's strings, they need 34", 2));
+    assert!(errors(src, 34).is_empty());
+}
+
+#[test]
+fn includes() {
+    // a shared library runs code as soon as it's loaded, so it's turned away before that
+    let src = "INC \"/nowhere/libevil.so\";\nINC evil;\nINC std;\nINC io;";
+    let program = sandboxed(src, Sandbox::new(64));
+    let errors: Vec<_> = program.check(&BorrowingModuleProvider).into_iter().filter(|d| d.is_error()).map(|d| d.message).collect();
+    assert_eq!(errors, ["the sandbox doesn't allow including `/nowhere/libevil.so`", "the sandbox doesn't allow including `evil`"]);
+    assert!(program.bytecode(BorrowingModuleProvider).is_err());
+
+    // one that's allowed is looked for like any other
+    let program = sandboxed(src, Sandbox::new(64).allow_module("/nowhere/libevil.so").allow_module("evil"));
+    let errors: Vec<_> = program.check(&BorrowingModuleProvider).into_iter().filter(|d| d.is_error()).map(|d| d.message).collect();
+    assert_eq!(errors, ["unknown module `/nowhere/libevil.so`", "unknown module `evil`"]);
+}
+
+#[test]
+fn lengths() {
+    let src = r#"
+        INC io;
+        INC std;
+        CALL malloc 16 p;
+        MOV 65 B&p;
+        CALL write 0, p, 1 n;
+        CALL write 0, p, 1000 m;
+        CALL errno ea;
+        CALL read 2, p, 100000 r;
+        CALL errno eb;
+        CALL write 0, p, -1 o;
+        CALL errno ec;
+        CALL addr_8 64 z;
+        CALL errno ed;
+        CALL addr_8 p a;
+        CALL write 0, p, 2000 m;
+        MOV 1 after;
+    "#;
+
+    // the whole range a module function gets has to be inside the memory, not just where it starts
+    let sandbox = Sandbox::new(64).allow("write").allow("read").allow("addr_8");
+    let (vars, error) = run(&sandboxed(src, sandbox), &["n", "m", "ea", "eb", "ec", "ed", "a", "after"]);
+    let oob = Error::OUT_OF_BOUNDS;
+    assert_eq!(vars, [1, 0, oob, oob, oob, oob, 65, 0]);
+    assert_eq!(error.unwrap().code, oob);
+}
@@ -0,0 +1,81 @@
+// programs run in process with their stdout, stderr and stdin swapped for buffers
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use rba::modules::BorrowingModuleProvider;
+use rba::{Capture, Options, Program, Redirect, Streams};
+
+fn program(file: &str) -> Program {
+    Program::parse(fs::read_to_string(file).unwrap()).unwrap_or_else(|e| panic!("{e}"))
+}
+
+#[test]
+fn jit() {
+    let out = Capture::new();
+    let mut engine = program("examples/fib.rbasm").compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone()).set_stdin(Cursor::new("10\n"));
+
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(out.text(), "55\n");
+}
+
+#[test]
+fn interpreter() {
+    let out = Capture::new();
+    let mut bytecode = program("examples/fib.rbasm").bytecode(BorrowingModuleProvider).unwrap();
+    bytecode.set_stdout(out.clone()).set_stdin(Cursor::new("10\n"));
+
+    unsafe { bytecode.run(&mut HashMap::new()) }.unwrap();
+    assert_eq!(out.text(), "55\n");
+}
+
+#[test]
+fn with_streams() {
+    // for a program that's only interpreted once
+    let out = Capture::new();
+    let mut streams = Streams { stdout: Box::new(out.clone()), stdin: Box::new(Cursor::new("10\n")), ..Streams::default() };
+
+    let fib = program("examples/fib.rbasm");
+    rba::with_streams(&mut streams, || unsafe { fib.interpret(BorrowingModuleProvider, &mut HashMap::new()).unwrap() }).unwrap();
+    assert_eq!(out.text(), "55\n");
+}
+
+#[test]
+fn stderr_and_stdout() {
+    let src = r#"
+        INC io;
+        CALL write 1, "oops", 4;
+        CALL write 0, "fine", 4;
+        OUT 7;
+    "#;
+
+    let (out, err) = (Capture::new(), Capture::new());
+    let mut engine = Program::parse(src).unwrap().compile(BorrowingModuleProvider).unwrap();
+    engine.set_stdout(out.clone()).set_stderr(err.clone());
+
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(out.text(), "fine7\n");
+    assert_eq!(err.text(), "oops");
+
+    // the streams stay with the engine
+    unsafe { engine.run() }.unwrap();
+    assert_eq!(out.take(), b"fine7\nfine7\n");
+}
+
+#[test]
+fn strings_outlive_the_program() {
+    // atol reads up to the nul
+    let src = r#"INC io; CALL write 0, "kept", 4; CALL atol "42" n; OUT n;"#;
+    for options in [Options::default(), Options { checked: true, ..Options::default() }] {
+        let out = Capture::new();
+        let mut program = Program::parse(src).unwrap();
+        program.set_options(options);
+        let mut bytecode = program.bytecode(BorrowingModuleProvider).unwrap();
+        drop(program);
+        bytecode.set_stdout(out.clone());
+
+        unsafe { bytecode.run(&mut HashMap::new()) }.unwrap();
+        assert_eq!(out.text(), "kept42\n");
+    }
+}